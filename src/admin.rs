@@ -0,0 +1,263 @@
+use std::sync::Arc;
+
+use axum::extract::{FromRequestParts, Query, State};
+use axum::http::request::Parts;
+use axum::http::{StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get, post};
+use axum::{Json, Router};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+
+use crate::db::PurgeFilter;
+use crate::state::AppState;
+use crate::usage::UsageReport;
+use crate::utils::normalize_path;
+use crate::versions;
+
+/// Path prefix reserved for admin endpoints, which are never generated
+pub const ADMIN_PREFIX: &str = "/_websim";
+
+/// Routes for the admin endpoints, nested under [`ADMIN_PREFIX`]
+pub fn router() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/usage", get(usage))
+        .route("/resources", delete(purge_resources))
+        .route("/versions", get(list_versions))
+        .route("/versions/diff", get(diff_versions))
+        .route("/versions/pin", post(pin_version))
+        .route("/versions/unpin", post(unpin_version))
+        .route("/versions/rollback", post(rollback_version))
+        .fallback(|| async { (StatusCode::NOT_FOUND, "Unknown admin endpoint") })
+}
+
+/// Guards the endpoints that change stored content, which need the configured `admin.token` as a bearer token.
+/// They are disabled without one, as scripts in generated pages are served from the same origin and could call them.
+struct AdminToken;
+
+impl FromRequestParts<Arc<AppState>> for AdminToken {
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        let Some(token) = &state.config.admin.token else {
+            return Err((
+                StatusCode::FORBIDDEN,
+                "Set admin.token in the config to enable this endpoint",
+            )
+                .into_response());
+        };
+
+        let sent = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        // Digests are compared so the time taken doesn't reveal how much of the token matched
+        match sent {
+            Some(sent) if Sha256::digest(sent) == Sha256::digest(token) => Ok(Self),
+            _ => {
+                warn!("Rejected admin request without a valid token");
+                Err((
+                    StatusCode::UNAUTHORIZED,
+                    [(header::WWW_AUTHENTICATE, "Bearer")],
+                    "Invalid or missing admin token",
+                )
+                    .into_response())
+            }
+        }
+    }
+}
+
+fn default_depth() -> usize {
+    1
+}
+
+#[derive(Debug, Deserialize)]
+struct UsageParams {
+    /// Number of path segments to group usage by
+    #[serde(default = "default_depth")]
+    depth: usize,
+}
+
+/// Reports aggregate token usage and cost as JSON.
+async fn usage(State(state): State<Arc<AppState>>, Query(params): Query<UsageParams>) -> Response {
+    match UsageReport::load(&state.db, params.depth).await {
+        Ok(report) => Json(report).into_response(),
+        Err(e) => internal_error(e, "Failed to build usage report"),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct PurgeParams {
+    path: Option<String>,
+    query: Option<String>,
+    prefix: Option<String>,
+    model: Option<String>,
+    mime_type: Option<String>,
+    /// Must be set to purge without any filter
+    #[serde(default)]
+    all: bool,
+}
+
+/// Invalidates stored resources matching the filter so they are regenerated on their next request.
+async fn purge_resources(
+    _: AdminToken,
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<PurgeParams>,
+) -> Response {
+    let filter = PurgeFilter {
+        path: params.path.map(|path| normalize_path(&path).to_string()),
+        query: params.query,
+        prefix: params.prefix,
+        model: params.model,
+        mime_type: params.mime_type,
+    };
+
+    if filter.is_empty() && !params.all {
+        return (
+            StatusCode::BAD_REQUEST,
+            "Set path, prefix, model or mime_type, or all=true to purge everything",
+        )
+            .into_response();
+    }
+
+    match state.db.purge(&filter).await {
+        Ok(purged) => {
+            info!(purged = %purged, filter = ?filter, "Purged resources");
+            Json(serde_json::json!({ "purged": purged })).into_response()
+        }
+        Err(e) => internal_error(e, "Failed to purge resources"),
+    }
+}
+
+/// Logs a failed admin operation and reports it as a 500
+fn internal_error(e: anyhow::Error, message: &str) -> Response {
+    warn!(error = %e, "{}", message);
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+}
+
+fn default_mime_type() -> String {
+    "text/html".to_string()
+}
+
+/// Identifies a stored resource, defaulting to the HTML page at a path without a query
+#[derive(Debug, Deserialize)]
+struct ResourceParams {
+    path: String,
+    #[serde(default)]
+    query: String,
+    #[serde(default = "default_mime_type")]
+    mime_type: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct VersionParams {
+    path: String,
+    #[serde(default)]
+    query: String,
+    #[serde(default = "default_mime_type")]
+    mime_type: String,
+    version: u32,
+}
+
+#[derive(Debug, Deserialize)]
+struct DiffParams {
+    path: String,
+    #[serde(default)]
+    query: String,
+    #[serde(default = "default_mime_type")]
+    mime_type: String,
+    from: u32,
+    to: u32,
+}
+
+/// Lists the stored versions of a resource as JSON.
+async fn list_versions(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<ResourceParams>,
+) -> Response {
+    let path = normalize_path(&params.path);
+    match state
+        .db
+        .versions(path, &params.query, &params.mime_type)
+        .await
+    {
+        Ok(versions) if versions.is_empty() => {
+            (StatusCode::NOT_FOUND, "No versions stored").into_response()
+        }
+        Ok(versions) => Json(versions).into_response(),
+        Err(e) => internal_error(e, "Failed to list versions"),
+    }
+}
+
+/// Renders a unified diff between two versions of a resource.
+async fn diff_versions(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<DiffParams>,
+) -> Response {
+    let path = normalize_path(&params.path);
+    let get = |version| {
+        state
+            .db
+            .get_version(path, &params.query, &params.mime_type, version)
+    };
+
+    match (get(params.from).await, get(params.to).await) {
+        (Ok(Some(from)), Ok(Some(to))) => {
+            versions::diff(path, (params.from, &from), (params.to, &to)).into_response()
+        }
+        (Ok(_), Ok(_)) => (StatusCode::NOT_FOUND, "Version not found").into_response(),
+        (Err(e), _) | (_, Err(e)) => internal_error(e, "Failed to read versions"),
+    }
+}
+
+/// Serves a version of a resource even after it is regenerated.
+async fn pin_version(
+    _: AdminToken,
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<VersionParams>,
+) -> Response {
+    let path = normalize_path(&params.path);
+    let result = state
+        .db
+        .pin(path, &params.query, &params.mime_type, params.version)
+        .await;
+    version_changed(result, "Failed to pin version")
+}
+
+/// Serves a version of a resource until it is next regenerated.
+async fn rollback_version(
+    _: AdminToken,
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<VersionParams>,
+) -> Response {
+    let path = normalize_path(&params.path);
+    let result = state
+        .db
+        .rollback(path, &params.query, &params.mime_type, params.version)
+        .await;
+    version_changed(result, "Failed to roll back version")
+}
+
+/// Goes back to serving the latest version of a resource.
+async fn unpin_version(
+    _: AdminToken,
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<ResourceParams>,
+) -> Response {
+    let path = normalize_path(&params.path);
+    let result = state.db.unpin(path, &params.query, &params.mime_type).await;
+    version_changed(result, "Failed to unpin version")
+}
+
+fn version_changed(result: anyhow::Result<bool>, message: &str) -> Response {
+    match result {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => (StatusCode::NOT_FOUND, "Version not found").into_response(),
+        Err(e) => internal_error(e, message),
+    }
+}
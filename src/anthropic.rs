@@ -0,0 +1,229 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use reqwest::StatusCode;
+use secrecy::{ExposeSecret, SecretString};
+use serde::{Deserialize, Serialize};
+
+use crate::backend::{
+    ApiError, ChatCompletionRequest, ChatCompletionResponse, Choice, ContentStream, Frame, Framing,
+    LlmBackend, Message, MessageRole, Usage, check_status, content_stream, log_request, read_json,
+};
+
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+/// Request body for the Anthropic Messages API
+#[derive(Debug, Serialize)]
+struct MessagesRequest<'a> {
+    model: &'a str,
+    max_tokens: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<String>,
+    messages: Vec<&'a Message>,
+    stream: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct MessagesResponse {
+    id: String,
+    model: String,
+    content: Vec<ContentBlock>,
+    stop_reason: Option<String>,
+    usage: Option<AnthropicUsage>,
+}
+
+/// Token counts, which are cumulative when streamed
+#[derive(Debug, Default, Deserialize)]
+struct AnthropicUsage {
+    #[serde(default)]
+    input_tokens: u64,
+    #[serde(default)]
+    output_tokens: u64,
+}
+
+impl From<AnthropicUsage> for Usage {
+    fn from(usage: AnthropicUsage) -> Self {
+        Usage {
+            prompt_tokens: usage.input_tokens,
+            completion_tokens: usage.output_tokens,
+            cost: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct MessageStart {
+    usage: Option<AnthropicUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ContentBlock {
+    #[serde(default)]
+    text: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorBody {
+    #[serde(rename = "type")]
+    kind: String,
+    message: String,
+}
+
+impl From<ErrorBody> for ApiError {
+    /// Maps an error sent mid-stream to the HTTP status the same error is returned with
+    fn from(error: ErrorBody) -> Self {
+        let status = match error.kind.as_str() {
+            "invalid_request_error" => StatusCode::BAD_REQUEST,
+            "authentication_error" => StatusCode::UNAUTHORIZED,
+            "permission_error" => StatusCode::FORBIDDEN,
+            "not_found_error" => StatusCode::NOT_FOUND,
+            "request_too_large" => StatusCode::PAYLOAD_TOO_LARGE,
+            "rate_limit_error" => StatusCode::TOO_MANY_REQUESTS,
+            "overloaded_error" => {
+                StatusCode::from_u16(529).unwrap_or(StatusCode::SERVICE_UNAVAILABLE)
+            }
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        ApiError::from_status(status, error.message, None)
+    }
+}
+
+/// Server-sent event payloads of a streamed Messages response
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum StreamEvent {
+    MessageStart {
+        message: MessageStart,
+    },
+    MessageDelta {
+        usage: Option<AnthropicUsage>,
+    },
+    ContentBlockDelta {
+        delta: ContentBlock,
+    },
+    MessageStop,
+    Error {
+        error: ErrorBody,
+    },
+    #[serde(other)]
+    Other,
+}
+
+fn parse_event(data: &str) -> Result<Frame> {
+    Ok(match serde_json::from_str::<StreamEvent>(data)? {
+        StreamEvent::MessageStart { message } => Frame {
+            usage: message.usage.map(Usage::from),
+            ..Default::default()
+        },
+        StreamEvent::MessageDelta { usage } => Frame {
+            usage: usage.map(Usage::from),
+            ..Default::default()
+        },
+        StreamEvent::ContentBlockDelta { delta } => Frame {
+            content: delta.text,
+            ..Default::default()
+        },
+        StreamEvent::MessageStop => Frame {
+            done: true,
+            ..Default::default()
+        },
+        StreamEvent::Error { error } => return Err(ApiError::from(error).into()),
+        StreamEvent::Other => Frame::default(),
+    })
+}
+
+/// Client for the Anthropic Messages API
+pub struct AnthropicClient {
+    client: reqwest::Client,
+    base_url: String,
+    api_key: SecretString,
+    max_tokens: u32,
+}
+
+impl AnthropicClient {
+    /// `base_url` is the API root that `/v1/messages` is appended to, e.g. `https://api.anthropic.com`
+    pub fn new(base_url: String, api_key: SecretString, max_tokens: u32) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            base_url,
+            api_key,
+            max_tokens,
+        }
+    }
+
+    async fn post(&self, request: &ChatCompletionRequest) -> Result<reqwest::Response> {
+        // System messages are passed separately from the conversation
+        let system: Vec<&str> = request
+            .messages
+            .iter()
+            .filter(|m| m.role == MessageRole::System)
+            .map(|m| m.content.as_str())
+            .collect();
+
+        let body = MessagesRequest {
+            model: &request.model,
+            max_tokens: self.max_tokens,
+            system: (!system.is_empty()).then(|| system.join("\n\n")),
+            messages: request
+                .messages
+                .iter()
+                .filter(|m| m.role != MessageRole::System)
+                .collect(),
+            stream: request.stream,
+        };
+
+        let url = format!("{}/v1/messages", self.base_url.trim_end_matches('/'));
+
+        Ok(self
+            .client
+            .post(url)
+            .header("x-api-key", self.api_key.expose_secret())
+            .header("anthropic-version", ANTHROPIC_VERSION)
+            .header("Content-Type", "application/json")
+            .json(&body)
+            .send()
+            .await?)
+    }
+}
+
+#[async_trait]
+impl LlmBackend for AnthropicClient {
+    async fn chat_completion(
+        &self,
+        request: ChatCompletionRequest,
+    ) -> Result<ChatCompletionResponse> {
+        log_request("Anthropic", &request);
+
+        let response = self.post(&request).await?;
+        let response = read_json::<MessagesResponse>(response).await?;
+
+        Ok(ChatCompletionResponse {
+            id: response.id,
+            model: response.model,
+            choices: vec![Choice {
+                message: Message {
+                    role: MessageRole::Assistant,
+                    content: response
+                        .content
+                        .into_iter()
+                        .map(|block| block.text)
+                        .collect(),
+                },
+                finish_reason: response.stop_reason,
+            }],
+            usage: response.usage.map(Usage::from),
+        })
+    }
+
+    async fn chat_completion_stream(
+        &self,
+        mut request: ChatCompletionRequest,
+    ) -> Result<ContentStream> {
+        request.stream = true;
+        log_request("Anthropic", &request);
+
+        let response = self.post(&request).await?;
+        let response = check_status(response).await?;
+
+        Ok(content_stream(response, Framing::Sse, parse_event))
+    }
+}
@@ -0,0 +1,599 @@
+use std::collections::{HashMap, VecDeque};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Context, Result, anyhow};
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures_util::StreamExt;
+use futures_util::stream::{self, BoxStream, Stream};
+use reqwest::StatusCode;
+use secrecy::SecretString;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use strum::{Display, EnumString};
+use tracing::debug;
+
+use crate::anthropic::AnthropicClient;
+use crate::config::BackendConfig;
+use crate::mock::MockBackend;
+use crate::ollama::OllamaClient;
+use crate::openai::OpenAiCompatibleClient;
+use crate::openrouter::OpenRouterClient;
+
+/// Message role in the chat conversation
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Display, EnumString)]
+#[strum(serialize_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum MessageRole {
+    System,
+    User,
+    Assistant,
+}
+
+/// Backend-agnostic chat completion request, serialized in the OpenAI chat format
+#[derive(Debug, Clone, Serialize)]
+pub struct ChatCompletionRequest {
+    pub model: String,
+    pub messages: Vec<Message>,
+    pub stream: bool,
+    /// Path and query being generated, for backends that don't rely on the prompts
+    #[serde(skip)]
+    pub path: String,
+    /// MIME type being generated, for backends that don't rely on the prompts
+    #[serde(skip)]
+    pub mime_type: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Message {
+    pub role: MessageRole,
+    pub content: String,
+}
+
+/// Backend-agnostic chat completion response, deserialized from the OpenAI chat format
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatCompletionResponse {
+    #[allow(dead_code)]
+    pub id: String,
+    #[allow(dead_code)]
+    pub model: String,
+    pub choices: Vec<Choice>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Choice {
+    pub message: Message,
+    #[allow(dead_code)]
+    pub finish_reason: Option<String>,
+}
+
+/// Tokens used by a chat completion, and what they cost if the API reports it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Usage {
+    #[serde(default)]
+    pub prompt_tokens: u64,
+    #[serde(default)]
+    pub completion_tokens: u64,
+    /// Cost in US dollars
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost: Option<f64>,
+}
+
+impl Usage {
+    /// Combines usage reported at different points of a stream.
+    /// Streamed counts are cumulative, so the largest count reported wins.
+    pub fn merge(&mut self, other: Usage) {
+        self.prompt_tokens = self.prompt_tokens.max(other.prompt_tokens);
+        self.completion_tokens = self.completion_tokens.max(other.completion_tokens);
+        self.cost = other.cost.or(self.cost);
+    }
+}
+
+/// An item of a streaming chat completion
+#[derive(Debug, Clone, PartialEq)]
+pub enum StreamDelta {
+    Content(String),
+    /// Usage reported by the API, usually once the content is complete
+    Usage(Usage),
+}
+
+/// Stream of content deltas from a streaming chat completion.
+/// Ends with an error if the upstream stream is interrupted before it signals completion.
+pub type ContentStream = Pin<Box<dyn Stream<Item = Result<StreamDelta>> + Send>>;
+
+/// A chat completion API that content can be generated with
+#[async_trait]
+pub trait LlmBackend: Send + Sync {
+    /// Sends a chat completion request and waits for the full response
+    async fn chat_completion(
+        &self,
+        request: ChatCompletionRequest,
+    ) -> Result<ChatCompletionResponse>;
+
+    /// Sends a chat completion request and yields content deltas as they arrive
+    async fn chat_completion_stream(&self, request: ChatCompletionRequest)
+    -> Result<ContentStream>;
+}
+
+/// Named backends that content types can select from
+pub type Backends = HashMap<String, Arc<dyn LlmBackend>>;
+
+/// Creates a backend for each configured entry
+pub fn build_backends(configs: &HashMap<String, BackendConfig>) -> Result<Backends> {
+    configs
+        .iter()
+        .map(|(name, config)| {
+            let backend = build_backend(config)
+                .with_context(|| format!("Failed to initialize backend: {}", name))?;
+            Ok((name.clone(), backend))
+        })
+        .collect()
+}
+
+fn build_backend(config: &BackendConfig) -> Result<Arc<dyn LlmBackend>> {
+    Ok(match config {
+        BackendConfig::OpenRouter {
+            api_key_env,
+            provider_sort,
+        } => Arc::new(OpenRouterClient::new(
+            required_api_key(api_key_env)?,
+            *provider_sort,
+        )),
+        BackendConfig::OpenAi {
+            base_url,
+            api_key_env,
+            auth_header,
+            auth_scheme,
+        } => Arc::new(OpenAiCompatibleClient::new(
+            base_url.clone(),
+            auth_header.clone(),
+            auth_scheme.clone(),
+            api_key_env.as_deref().map(required_api_key).transpose()?,
+        )),
+        BackendConfig::Ollama { base_url } => Arc::new(OllamaClient::new(base_url.clone())),
+        BackendConfig::Anthropic {
+            base_url,
+            api_key_env,
+            max_tokens,
+        } => Arc::new(AnthropicClient::new(
+            base_url.clone(),
+            required_api_key(api_key_env)?,
+            *max_tokens,
+        )),
+        BackendConfig::Mock { delay_ms } => {
+            Arc::new(MockBackend::new(Duration::from_millis(*delay_ms)))
+        }
+    })
+}
+
+fn required_api_key(env: &str) -> Result<SecretString> {
+    let api_key =
+        std::env::var(env).with_context(|| format!("{} environment variable must be set", env))?;
+    Ok(api_key.into())
+}
+
+/// Logs a request in a more readable format
+pub(crate) fn log_request(backend: &str, request: &ChatCompletionRequest) {
+    debug!("{} API request:", backend);
+    debug!("  Model: {}", request.model);
+    debug!("  Stream: {}", request.stream);
+    debug!("  Messages:");
+    for (i, msg) in request.messages.iter().enumerate() {
+        debug!("    [{}] Role: {}", i, msg.role);
+        debug!("    [{}] Content:\n{}", i, msg.content);
+    }
+}
+
+/// How a streaming response body separates its messages
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Framing {
+    /// Server-sent events, yielding the `data:` payload of each event
+    Sse,
+    /// Newline-delimited JSON, yielding each non-empty line
+    Ndjson,
+}
+
+/// Incrementally splits a streaming response body into frames
+#[derive(Debug)]
+struct FrameDecoder {
+    framing: Framing,
+    buf: Vec<u8>,
+    data: Vec<String>,
+}
+
+impl FrameDecoder {
+    fn new(framing: Framing) -> Self {
+        Self {
+            framing,
+            buf: Vec::new(),
+            data: Vec::new(),
+        }
+    }
+
+    /// Feeds bytes into the decoder, returning any frames completed by them
+    fn push(&mut self, bytes: &[u8]) -> Vec<String> {
+        self.buf.extend_from_slice(bytes);
+
+        let mut frames = Vec::new();
+        while let Some(pos) = self.buf.iter().position(|&b| b == b'\n') {
+            // Only complete lines are decoded so multi-byte characters are never split
+            let line: Vec<u8> = self.buf.drain(..=pos).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\r', '\n']);
+
+            match self.framing {
+                Framing::Ndjson => {
+                    if !line.is_empty() {
+                        frames.push(line.to_string());
+                    }
+                }
+                Framing::Sse => {
+                    if line.is_empty() {
+                        // Blank line dispatches the event
+                        if !self.data.is_empty() {
+                            frames.push(self.data.join("\n"));
+                            self.data.clear();
+                        }
+                    } else if let Some(value) = line.strip_prefix("data:") {
+                        self.data
+                            .push(value.strip_prefix(' ').unwrap_or(value).to_string());
+                    }
+                    // Comment lines (": OPENROUTER PROCESSING") and other fields are ignored
+                }
+            }
+        }
+
+        frames
+    }
+}
+
+/// A parsed frame of a streaming response
+#[derive(Debug, Default)]
+pub(crate) struct Frame {
+    pub content: String,
+    pub usage: Option<Usage>,
+    /// Set once the backend signals the generation is complete
+    pub done: bool,
+}
+
+/// Parses a single frame, returning an error for upstream error payloads
+pub(crate) type FrameParser = fn(&str) -> Result<Frame>;
+
+struct StreamState {
+    bytes: BoxStream<'static, reqwest::Result<Bytes>>,
+    decoder: FrameDecoder,
+    parse: FrameParser,
+    pending: VecDeque<String>,
+    output: VecDeque<StreamDelta>,
+    done: bool,
+}
+
+/// Error reported by a backend API, classified so callers can decide whether to retry
+/// and which status to report to the client
+#[derive(Debug, thiserror::Error)]
+pub enum ApiError {
+    /// Invalid credentials, insufficient credits or missing permissions
+    #[error("Authentication failed: {0}")]
+    Auth(String),
+    #[error("Rate limited: {message}")]
+    RateLimited {
+        message: String,
+        retry_after: Option<Duration>,
+    },
+    /// The prompt or output was flagged by content moderation
+    #[error("Flagged by content moderation: {0}")]
+    Moderation(String),
+    /// The prompt, including reference materials, exceeds the model's context window
+    #[error("Context length exceeded: {0}")]
+    ContextLength(String),
+    /// The provider behind the API failed or is unavailable
+    #[error("Upstream provider error ({status}): {message}")]
+    Upstream {
+        status: StatusCode,
+        message: String,
+        retry_after: Option<Duration>,
+    },
+    /// The API rejected the request for any other reason
+    #[error("Invalid request ({status}): {message}")]
+    InvalidRequest { status: StatusCode, message: String },
+    #[error("Request timed out: {0}")]
+    Timeout(String),
+}
+
+impl ApiError {
+    /// Classifies an error from its HTTP status, or the status-like code in an error body
+    pub fn from_status(status: StatusCode, message: String, retry_after: Option<Duration>) -> Self {
+        let lower = message.to_lowercase();
+        match status.as_u16() {
+            401 | 402 => Self::Auth(message),
+            403 if lower.contains("moderation") || lower.contains("flagged") => {
+                Self::Moderation(message)
+            }
+            403 => Self::Auth(message),
+            408 | 504 => Self::Timeout(message),
+            429 => Self::RateLimited {
+                message,
+                retry_after,
+            },
+            400 | 413
+                if lower.contains("context length")
+                    || lower.contains("context_length")
+                    || lower.contains("context window")
+                    || lower.contains("too many tokens")
+                    || lower.contains("prompt is too long") =>
+            {
+                Self::ContextLength(message)
+            }
+            500..=599 => Self::Upstream {
+                status,
+                message,
+                retry_after,
+            },
+            _ => Self::InvalidRequest { status, message },
+        }
+    }
+
+    /// Classifies an error response body in any of the supported backends' formats,
+    /// preferring an error code in the body over the HTTP status
+    pub fn from_body(status: StatusCode, body: &str, retry_after: Option<Duration>) -> Self {
+        let json = serde_json::from_str::<serde_json::Value>(body).unwrap_or_default();
+        let error = &json["error"];
+
+        let message = error["message"]
+            .as_str()
+            .or_else(|| error.as_str())
+            .or_else(|| json["message"].as_str())
+            .unwrap_or(body)
+            .to_string();
+
+        let status = error["code"]
+            .as_u64()
+            .and_then(|code| u16::try_from(code).ok())
+            .and_then(|code| StatusCode::from_u16(code).ok())
+            .filter(|code| !code.is_success())
+            .unwrap_or(status);
+
+        Self::from_status(status, message, retry_after)
+    }
+
+    /// Whether the request may succeed if retried
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            Self::RateLimited { .. } | Self::Upstream { .. } | Self::Timeout(_)
+        )
+    }
+
+    /// Delay requested by the API before retrying
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Self::RateLimited { retry_after, .. } | Self::Upstream { retry_after, .. } => {
+                *retry_after
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Parses a `Retry-After` header given either as delay seconds or an HTTP date
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(secs) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = httpdate::parse_http_date(value.trim()).ok()?;
+    Some(date.duration_since(SystemTime::now()).unwrap_or_default())
+}
+
+/// Checks the status of a response before its body is read
+pub(crate) async fn check_status(response: reqwest::Response) -> Result<reqwest::Response> {
+    let status = response.status();
+    if !status.is_success() {
+        let retry_after = response
+            .headers()
+            .get("retry-after")
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_retry_after);
+        let body = response.text().await.unwrap_or_default();
+        return Err(ApiError::from_body(status, &body, retry_after).into());
+    }
+    Ok(response)
+}
+
+/// Reads a JSON response body, surfacing error bodies as [`ApiError`]s even when
+/// they are sent with a success status
+pub(crate) async fn read_json<T: DeserializeOwned>(response: reqwest::Response) -> Result<T> {
+    let response = check_status(response).await?;
+    let status = response.status();
+    let body = response.text().await?;
+
+    match serde_json::from_str::<T>(&body) {
+        Ok(value) => Ok(value),
+        Err(e) => {
+            let json = serde_json::from_str::<serde_json::Value>(&body).unwrap_or_default();
+            if json.get("error").is_some() {
+                // An error code in the body takes precedence over the success status
+                Err(ApiError::from_body(status, &body, None).into())
+            } else {
+                Err(e.into())
+            }
+        }
+    }
+}
+
+/// Turns a streaming response body into content deltas
+pub(crate) fn content_stream(
+    response: reqwest::Response,
+    framing: Framing,
+    parse: FrameParser,
+) -> ContentStream {
+    let state = StreamState {
+        bytes: response.bytes_stream().boxed(),
+        decoder: FrameDecoder::new(framing),
+        parse,
+        pending: VecDeque::new(),
+        output: VecDeque::new(),
+        done: false,
+    };
+
+    let stream = stream::unfold(state, |mut state| async move {
+        loop {
+            if let Some(delta) = state.output.pop_front() {
+                return Some((Ok(delta), state));
+            }
+
+            if state.done {
+                return None;
+            }
+
+            if let Some(data) = state.pending.pop_front() {
+                let frame = match (state.parse)(&data) {
+                    Ok(frame) => frame,
+                    Err(e) => {
+                        state.done = true;
+                        return Some((Err(e), state));
+                    }
+                };
+
+                state.done = frame.done;
+                if !frame.content.is_empty() {
+                    state.output.push_back(StreamDelta::Content(frame.content));
+                }
+                if let Some(usage) = frame.usage {
+                    state.output.push_back(StreamDelta::Usage(usage));
+                }
+                continue;
+            }
+
+            match state.bytes.next().await {
+                Some(Ok(bytes)) => {
+                    let frames = state.decoder.push(&bytes);
+                    state.pending.extend(frames);
+                }
+                Some(Err(e)) => {
+                    state.done = true;
+                    return Some((Err(e.into()), state));
+                }
+                None => {
+                    state.done = true;
+                    return Some((Err(anyhow!("Stream ended before completion")), state));
+                }
+            }
+        }
+    });
+
+    Box::pin(stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sse_decoder() {
+        let mut decoder = FrameDecoder::new(Framing::Sse);
+
+        // Comments are skipped and partial lines are buffered until complete
+        assert!(
+            decoder
+                .push(b": OPENROUTER PROCESSING\n\ndata: {\"a\"")
+                .is_empty()
+        );
+        assert_eq!(decoder.push(b":1}\n\n"), vec![r#"{"a":1}"#]);
+
+        // CRLF line endings, event fields and multiple events in one read
+        assert_eq!(
+            decoder.push(b"event: delta\r\ndata: one\r\n\r\ndata: [DONE]\n\n"),
+            vec!["one", "[DONE]"]
+        );
+
+        // Multi-line data fields are joined with newlines
+        assert_eq!(decoder.push(b"data: a\ndata: b\n\n"), vec!["a\nb"]);
+
+        // Multi-byte characters split across reads
+        let bytes = "data: café\n\n".as_bytes();
+        assert!(decoder.push(&bytes[..10]).is_empty());
+        assert_eq!(decoder.push(&bytes[10..]), vec!["café"]);
+    }
+
+    #[test]
+    fn test_api_error_classification() {
+        let classify = |status: u16, body: &str| {
+            ApiError::from_body(StatusCode::from_u16(status).unwrap(), body, None)
+        };
+
+        // OpenRouter, including error codes sent in the body with a success status
+        assert!(matches!(
+            classify(401, r#"{"error":{"code":401,"message":"No auth credentials found"}}"#),
+            ApiError::Auth(m) if m == "No auth credentials found"
+        ));
+        assert!(matches!(
+            classify(
+                200,
+                r#"{"error":{"code":429,"message":"Rate limit exceeded"}}"#
+            ),
+            ApiError::RateLimited { .. }
+        ));
+        assert!(matches!(
+            classify(
+                403,
+                r#"{"error":{"code":403,"message":"Input was flagged by moderation"}}"#
+            ),
+            ApiError::Moderation(_)
+        ));
+        assert!(matches!(
+            classify(
+                400,
+                r#"{"error":{"message":"This endpoint's maximum context length is 8192 tokens"}}"#
+            ),
+            ApiError::ContextLength(_)
+        ));
+        assert!(matches!(
+            classify(
+                200,
+                r#"{"error":{"code":502,"message":"Provider returned error"}}"#
+            ),
+            ApiError::Upstream { .. }
+        ));
+
+        // Anthropic and Ollama error bodies
+        assert!(matches!(
+            classify(529, r#"{"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#),
+            ApiError::Upstream { message, .. } if message == "Overloaded"
+        ));
+        assert!(matches!(
+            classify(404, r#"{"error":"model 'llama9' not found"}"#),
+            ApiError::InvalidRequest { message, .. } if message == "model 'llama9' not found"
+        ));
+
+        // Plain text bodies
+        assert!(matches!(
+            classify(504, "Gateway Timeout"),
+            ApiError::Timeout(_)
+        ));
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(parse_retry_after("3"), Some(Duration::from_secs(3)));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
+    #[test]
+    fn test_ndjson_decoder() {
+        let mut decoder = FrameDecoder::new(Framing::Ndjson);
+
+        assert!(decoder.push(b"{\"a\":").is_empty());
+        assert_eq!(
+            decoder.push(b"1}\n\n{\"b\":2}\r\n"),
+            vec![r#"{"a":1}"#, r#"{"b":2}"#]
+        );
+    }
+}
@@ -0,0 +1,85 @@
+use anyhow::Result;
+use serde::Serialize;
+use strum::Display;
+
+use crate::config::{BudgetConfig, BudgetLimit};
+use crate::db::Database;
+use crate::usage::UsageTotals;
+
+/// Usage within the current budget periods
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct Spending {
+    pub today: UsageTotals,
+    pub this_month: UsageTotals,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum BudgetPeriod {
+    Daily,
+    Monthly,
+}
+
+impl BudgetLimit {
+    /// Whether spending has reached either the token or the cost limit
+    pub fn is_reached(&self, totals: &UsageTotals) -> bool {
+        self.tokens
+            .is_some_and(|tokens| totals.prompt_tokens + totals.completion_tokens >= tokens)
+            || self.cost.is_some_and(|cost| totals.cost >= cost)
+    }
+}
+
+/// Returns the budget period whose limit has been reached, if any.
+/// Spending is read from the usage recorded in the database, so budgets carry over restarts.
+pub async fn exhausted_period(
+    db: &Database,
+    budget: &BudgetConfig,
+) -> Result<Option<BudgetPeriod>> {
+    let spending = db.spending().await?;
+
+    Ok(if budget.daily.is_reached(&spending.today) {
+        Some(BudgetPeriod::Daily)
+    } else if budget.monthly.is_reached(&spending.this_month) {
+        Some(BudgetPeriod::Monthly)
+    } else {
+        None
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_budget_limit_is_reached() {
+        let totals = UsageTotals {
+            generations: 2,
+            prompt_tokens: 600,
+            completion_tokens: 400,
+            cost: 0.5,
+        };
+
+        assert!(!BudgetLimit::default().is_reached(&totals));
+        assert!(
+            BudgetLimit {
+                tokens: Some(1000),
+                cost: None
+            }
+            .is_reached(&totals)
+        );
+        assert!(
+            !BudgetLimit {
+                tokens: Some(1001),
+                cost: Some(0.51)
+            }
+            .is_reached(&totals)
+        );
+        assert!(
+            BudgetLimit {
+                tokens: Some(5000),
+                cost: Some(0.5)
+            }
+            .is_reached(&totals)
+        );
+    }
+}
@@ -0,0 +1,108 @@
+use std::time::SystemTime;
+
+use axum::http::{HeaderMap, header};
+use sha2::{Digest, Sha256};
+
+/// Strong entity tag derived from content, so it stays the same until the content changes
+pub fn etag(content: &str) -> String {
+    let digest = Sha256::digest(content.as_bytes());
+    let hex: String = digest[..16].iter().map(|b| format!("{:02x}", b)).collect();
+    format!("\"{}\"", hex)
+}
+
+/// Evaluates `If-None-Match` and `If-Modified-Since` as RFC 9110 does for GET requests, returning
+/// whether the client's copy is current and `304 Not Modified` can be sent.
+/// `If-Modified-Since` is ignored when `If-None-Match` is present.
+pub fn is_not_modified(headers: &HeaderMap, etag: &str, last_modified: Option<SystemTime>) -> bool {
+    if headers.contains_key(header::IF_NONE_MATCH) {
+        return headers
+            .get_all(header::IF_NONE_MATCH)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .map(str::trim)
+            .any(|tag| tag == "*" || weak_eq(tag, etag));
+    }
+
+    let (Some(last_modified), Some(since)) = (
+        last_modified,
+        headers
+            .get(header::IF_MODIFIED_SINCE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| httpdate::parse_http_date(v).ok()),
+    ) else {
+        return false;
+    };
+    last_modified <= since
+}
+
+/// Weak comparison, under which tags match if their opaque parts do regardless of `W/` prefixes
+fn weak_eq(a: &str, b: &str) -> bool {
+    a.trim_start_matches("W/") == b.trim_start_matches("W/")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    fn headers(pairs: &[(header::HeaderName, &'static str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.clone(), HeaderValue::from_static(value)))
+            .collect()
+    }
+
+    #[test]
+    fn test_etag_is_stable() {
+        assert_eq!(etag("<html>"), etag("<html>"));
+        assert_ne!(etag("<html>"), etag("<html> "));
+        assert!(etag("").starts_with('"') && etag("").ends_with('"'));
+    }
+
+    #[test]
+    fn test_is_not_modified() {
+        let tag = etag("content");
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let since =
+            |secs: u64| httpdate::fmt_http_date(SystemTime::UNIX_EPOCH + Duration::from_secs(secs));
+
+        assert!(!is_not_modified(&HeaderMap::new(), &tag, Some(modified)));
+
+        let mut matching = headers(&[(header::IF_NONE_MATCH, "\"other\"")]);
+        matching.append(
+            header::IF_NONE_MATCH,
+            HeaderValue::from_str(&format!("W/{}", tag)).unwrap(),
+        );
+        assert!(is_not_modified(&matching, &tag, None));
+        assert!(is_not_modified(
+            &headers(&[(header::IF_NONE_MATCH, "*")]),
+            &tag,
+            None
+        ));
+
+        let mut dates = HeaderMap::new();
+        dates.insert(
+            header::IF_MODIFIED_SINCE,
+            HeaderValue::from_str(&since(1_700_000_000)).unwrap(),
+        );
+        assert!(is_not_modified(&dates, &tag, Some(modified)));
+        assert!(!is_not_modified(&dates, &tag, None));
+        dates.insert(
+            header::IF_MODIFIED_SINCE,
+            HeaderValue::from_str(&since(1_699_999_999)).unwrap(),
+        );
+        assert!(!is_not_modified(&dates, &tag, Some(modified)));
+
+        // A mismatched entity tag takes precedence over the date
+        dates.insert(header::IF_NONE_MATCH, HeaderValue::from_static("\"other\""));
+        dates.insert(
+            header::IF_MODIFIED_SINCE,
+            HeaderValue::from_str(&since(1_800_000_000)).unwrap(),
+        );
+        assert!(!is_not_modified(&dates, &tag, Some(modified)));
+    }
+}
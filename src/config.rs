@@ -1,71 +1,464 @@
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use anyhow::Result;
-use minijinja::Environment;
-use serde::Deserialize;
+use anyhow::{Context, Result, bail};
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::openrouter::ProviderSort;
 
 /// Configuration for a single content type
 #[derive(Debug, Clone, Deserialize)]
 pub struct ContentTypeConfig {
+    /// Name of the entry in `backends` used to generate this content type
+    #[serde(default = "default_backend")]
+    pub backend: String,
     pub model: String,
     pub system_prompt: String,
     pub content_type_header: String,
     pub extensions: Vec<String>,
+    /// Whether to stream generated content to the client as it arrives
+    #[serde(default = "default_stream")]
+    pub stream: bool,
+    /// Seconds an attempt may take before it is abandoned: until the first content arrives when streaming,
+    /// and until the whole completion is received otherwise
+    pub timeout_secs: Option<u64>,
+    /// Retries per model for transient failures (429, 5xx, timeouts and connection errors)
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+    /// Models tried in order if `model` still fails after retries
+    #[serde(default)]
+    pub fallback_models: Vec<String>,
+    /// Seconds generated content is served from the cache before being regenerated, forever if not set
+    pub ttl_secs: Option<u64>,
+    /// `Cache-Control` header sent with cached content, e.g. `no-cache` to have browsers revalidate on each visit
+    pub cache_control: Option<String>,
+    /// Pages linked from each generated HTML page to generate in the background ahead of a click, none by default
+    #[serde(default)]
+    pub prefetch_links: usize,
+    /// Whether stored pages at the referer, base and parent paths are given to the model as reference material
+    #[serde(default = "default_reference_materials")]
+    pub reference_materials: bool,
+    /// Template user prompts for this content type are rendered from, instead of the top-level one
+    pub user_prompt_template: Option<PromptTemplate>,
+}
+
+fn default_stream() -> bool {
+    true
+}
+
+fn default_max_retries() -> u32 {
+    2
+}
+
+fn default_reference_materials() -> bool {
+    true
+}
+
+fn default_backend() -> String {
+    "openrouter".to_string()
+}
+
+/// Configuration for a chat completion API that content types can select by name.
+/// API keys are always read from environment variables rather than the config file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum BackendConfig {
+    OpenRouter {
+        #[serde(default = "default_openrouter_api_key_env")]
+        api_key_env: String,
+        #[serde(default = "default_provider_sort")]
+        provider_sort: Option<ProviderSort>,
+    },
+    /// Any server implementing the OpenAI chat completions API
+    OpenAi {
+        base_url: String,
+        api_key_env: Option<String>,
+        #[serde(default = "default_auth_header")]
+        auth_header: String,
+        #[serde(default = "default_auth_scheme")]
+        auth_scheme: String,
+    },
+    Ollama {
+        #[serde(default = "default_ollama_base_url")]
+        base_url: String,
+    },
+    Anthropic {
+        #[serde(default = "default_anthropic_base_url")]
+        base_url: String,
+        #[serde(default = "default_anthropic_api_key_env")]
+        api_key_env: String,
+        #[serde(default = "default_max_tokens")]
+        max_tokens: u32,
+    },
+    /// Offline backend returning deterministic content, for tests and demos
+    Mock {
+        /// Simulated generation latency in milliseconds
+        #[serde(default)]
+        delay_ms: u64,
+    },
+}
+
+fn default_openrouter_api_key_env() -> String {
+    "WEBSIM_API_KEY".to_string()
+}
+
+fn default_provider_sort() -> Option<ProviderSort> {
+    Some(ProviderSort::Latency)
+}
+
+fn default_auth_header() -> String {
+    "Authorization".to_string()
+}
+
+fn default_auth_scheme() -> String {
+    "Bearer".to_string()
+}
+
+fn default_ollama_base_url() -> String {
+    "http://localhost:11434".to_string()
+}
+
+fn default_anthropic_base_url() -> String {
+    "https://api.anthropic.com".to_string()
+}
+
+fn default_anthropic_api_key_env() -> String {
+    "ANTHROPIC_API_KEY".to_string()
+}
+
+fn default_max_tokens() -> u32 {
+    16384
+}
+
+/// Name of the backend all content types use when running with `--mock`
+pub const MOCK_BACKEND: &str = "mock";
+
+/// Used when no `backends` are configured, matching the original OpenRouter-only setup
+fn default_backends() -> HashMap<String, BackendConfig> {
+    HashMap::from([(
+        default_backend(),
+        BackendConfig::OpenRouter {
+            api_key_env: default_openrouter_api_key_env(),
+            provider_sort: default_provider_sort(),
+        },
+    )])
 }
 
 impl ContentTypeConfig {
-    pub fn user_prompt_builder(&self, path: String) -> UserPromptBuilder {
-        UserPromptBuilder {
-            path,
-            headers: None,
-            reference_materials: None,
+    /// How long generated content is served from the cache, forever if `None`
+    pub fn ttl(&self) -> Option<Duration> {
+        self.ttl_secs.map(Duration::from_secs)
+    }
+
+    /// SHA-256 hex digest identifying the system prompt content was generated with
+    pub fn system_prompt_hash(&self) -> String {
+        Sha256::digest(self.system_prompt.as_bytes())
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+}
+
+/// Root configuration structure
+#[derive(Debug, Deserialize, Clone)]
+pub struct WebSimConfig {
+    #[serde(default = "default_backends")]
+    pub backends: HashMap<String, BackendConfig>,
+    /// Content types keyed by MIME type, in config order, which breaks ties in content negotiation
+    pub content_types: IndexMap<String, ContentTypeConfig>,
+    /// How long a request waits on an in-flight generation for the same path before giving up with a 503
+    #[serde(default = "default_in_flight_timeout_secs")]
+    pub in_flight_timeout_secs: u64,
+    /// Spending caps, unlimited if not set
+    #[serde(default)]
+    pub budget: Option<BudgetConfig>,
+    #[serde(default)]
+    pub cache: CacheConfig,
+    #[serde(default)]
+    pub prefetch: PrefetchConfig,
+    #[serde(default)]
+    pub admin: AdminConfig,
+    /// Overrides for requests whose path matches, the first matching route applying
+    #[serde(default)]
+    pub routes: Vec<RouteConfig>,
+    /// Description of the simulated world, given to the model in every prompt
+    pub world: Option<WorldConfig>,
+    /// Other sites served alongside this one, keyed by name, the first matching a request serving it
+    #[serde(default)]
+    pub sites: IndexMap<String, SiteConfig>,
+    /// Template user prompts are rendered from, for content types without their own
+    pub user_prompt_template: Option<PromptTemplate>,
+}
+
+impl WebSimConfig {
+    /// Config for one of the other sites: its own content types (the top-level ones if not set), routes and world,
+    /// with the backends and limits of the whole server
+    pub fn for_site(&self, site: &SiteConfig) -> Self {
+        Self {
+            content_types: site
+                .content_types
+                .clone()
+                .unwrap_or_else(|| self.content_types.clone()),
+            routes: site.routes.clone(),
+            world: site.world.clone(),
+            sites: IndexMap::new(),
+            ..self.clone()
+        }
+    }
+
+    /// Config for the site named `name`, this one for the default site `""`
+    pub fn site(&self, name: &str) -> Result<Self> {
+        if name.is_empty() {
+            return Ok(self.clone());
+        }
+        match self.sites.get(name) {
+            Some(site) => Ok(self.for_site(site)),
+            None => bail!("Unknown site: {}", name),
+        }
+    }
+
+    /// Whether generated pages record the facts they establish for later pages
+    pub fn records_facts(&self) -> bool {
+        self.world.as_ref().is_some_and(|world| world.facts)
+    }
+
+    /// Loads a config file, in any format the file's extension selects
+    pub fn load(path: &Path) -> Result<Self> {
+        let path_str = path.display().to_string();
+        let mut config: Self = ::config::Config::builder()
+            .add_source(::config::File::with_name(&path_str))
+            .build()
+            .with_context(|| format!("Failed to load config from: {}", path_str))?
+            .try_deserialize()
+            .with_context(|| format!("Failed to parse config from: {}", path_str))?;
+
+        config.resolve_template_files(path.parent().unwrap_or(Path::new("")));
+        Ok(config)
+    }
+
+    /// Makes template files relative to `base_dir`, the directory of the config file naming them
+    fn resolve_template_files(&mut self, base_dir: &Path) {
+        let site_content_types = self
+            .sites
+            .values_mut()
+            .filter_map(|site| site.content_types.as_mut());
+        let content_types = std::iter::once(&mut self.content_types)
+            .chain(site_content_types)
+            .flat_map(|content_types| content_types.values_mut());
+        let templates = content_types
+            .filter_map(|content_type| content_type.user_prompt_template.as_mut())
+            .chain(self.user_prompt_template.as_mut());
+
+        for template in templates {
+            if let PromptTemplate::File { file } = template {
+                *file = base_dir.join(&*file);
+            }
         }
     }
 }
 
-/// Builder for constructing user prompts
-pub struct UserPromptBuilder {
-    path: String,
-    headers: Option<String>,
-    reference_materials: Option<String>,
+/// A minijinja template, given inline or as the path of a file relative to the config file
+#[derive(Debug, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum PromptTemplate {
+    Inline(String),
+    File { file: PathBuf },
 }
 
-impl UserPromptBuilder {
-    pub fn headers(mut self, headers: String) -> Self {
-        self.headers = Some(headers);
-        self
+impl PromptTemplate {
+    /// Source of the template, read from its file if it has one
+    pub fn source(&self) -> Result<String> {
+        match self {
+            Self::Inline(source) => Ok(source.clone()),
+            Self::File { file } => std::fs::read_to_string(file)
+                .with_context(|| format!("Failed to read template: {}", file.display())),
+        }
     }
+}
 
-    pub fn reference_materials(mut self, reference_materials: String) -> Self {
-        self.reference_materials = Some(reference_materials);
-        self
+fn default_in_flight_timeout_secs() -> u64 {
+    120
+}
+
+/// Limits on cached content, enforced by a background task while the server runs
+#[derive(Debug, Deserialize, Clone)]
+pub struct CacheConfig {
+    /// Total size of cached content above which the least recently served resources are evicted, and of stored
+    /// versions above which the oldest ones no longer served are, unlimited if not set
+    pub max_bytes: Option<u64>,
+    /// Seconds between evictions of expired and least recently served resources
+    #[serde(default = "default_eviction_interval_secs")]
+    pub eviction_interval_secs: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            max_bytes: None,
+            eviction_interval_secs: default_eviction_interval_secs(),
+        }
     }
+}
 
-    pub fn build(self) -> Result<String> {
-        const USER_PROMPT_TEMPLATE: &str = r#"Generate content for path: {{ path }}
+fn default_eviction_interval_secs() -> u64 {
+    60
+}
 
-The following materials are context-only. They are **not part of the output**.
-Use them only to stay consistent with style or data conventions.
+/// Access to the admin endpoints that change stored content
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct AdminConfig {
+    /// Bearer token the endpoints require, which are disabled if not set
+    pub token: Option<String>,
+}
 
-Headers: {{ headers }}
-Reference materials: {{ reference_materials }}"#;
+/// A site served alongside the top-level one, selected by `Host` header or path prefix, with its own
+/// content types and its own namespace of stored resources
+#[derive(Debug, Deserialize, Clone)]
+pub struct SiteConfig {
+    /// Host names the site is served on, without the port, e.g. `news.localhost`
+    #[serde(default)]
+    pub hosts: Vec<String>,
+    /// Path prefix the site is served under, matching whole segments, e.g. `/api`
+    pub path_prefix: Option<String>,
+    pub world: Option<WorldConfig>,
+    pub content_types: Option<IndexMap<String, ContentTypeConfig>>,
+    #[serde(default)]
+    pub routes: Vec<RouteConfig>,
+}
 
-        let mut env = Environment::new();
-        env.add_template("user_prompt", USER_PROMPT_TEMPLATE)?;
+/// The "bible" of a simulated world, which every page generated for the site stays consistent with
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct WorldConfig {
+    /// Name of the site, e.g. `The Daily Planet`
+    pub name: Option<String>,
+    /// Free-form description of the site and the world it is part of
+    pub description: Option<String>,
+    /// Tone of voice, e.g. `breathless tabloid`
+    pub tone: Option<String>,
+    /// When the site is set, e.g. `1999` or `the far future`
+    pub era: Option<String>,
+    /// Language and region content is written for, e.g. `en-GB`
+    pub locale: Option<String>,
+    /// People, places, products and other entities pages may refer to, with a description of each
+    #[serde(default)]
+    pub entities: IndexMap<String, String>,
+    /// Whether the model records the facts each page establishes, which are given to it for later pages
+    #[serde(default)]
+    pub facts: bool,
+    /// Most recently recorded facts included in each prompt
+    #[serde(default = "default_max_facts")]
+    pub max_facts: usize,
+}
 
-        let template = env.get_template("user_prompt")?;
-        let prompt = template.render(minijinja::context! {
-            path => self.path,
-            headers => self.headers.unwrap_or_else(|| "none".to_string()),
-            reference_materials => self.reference_materials.unwrap_or_else(|| "none".to_string()),
-        })?;
+impl WorldConfig {
+    /// Describes the world for the user prompt, leaving out what isn't set
+    pub fn describe(&self) -> String {
+        let mut description = Vec::new();
+        let properties = [
+            ("Site", &self.name),
+            ("Tone", &self.tone),
+            ("Era", &self.era),
+            ("Locale", &self.locale),
+        ];
+        for (label, value) in properties {
+            if let Some(value) = value {
+                description.push(format!("{}: {}", label, value));
+            }
+        }
+        if let Some(text) = &self.description {
+            description.push(format!("\n{}", text.trim_end()));
+        }
+        if !self.entities.is_empty() {
+            description.push("\nKey entities:".to_string());
+            for (name, entity) in &self.entities {
+                description.push(format!("- {}: {}", name, entity));
+            }
+        }
+        description.join("\n").trim_start().to_string()
+    }
+}
+
+fn default_max_facts() -> usize {
+    100
+}
 
-        Ok(prompt)
+/// Overrides for requests to matching paths, given by either a glob or a regex.
+/// Unset fields keep the value from the content type being served.
+#[derive(Debug, Deserialize, Clone)]
+pub struct RouteConfig {
+    /// Glob matched against the whole path, where `*` matches within a segment and `**` across segments,
+    /// e.g. `/api/**` or `/blog/*/comments`
+    pub path: Option<String>,
+    /// Regex matched against the path instead of a glob, unanchored unless it uses `^` and `$`
+    pub regex: Option<String>,
+    /// MIME type of the configured content type to serve, instead of negotiating one
+    pub content_type: Option<String>,
+    pub backend: Option<String>,
+    pub model: Option<String>,
+    pub system_prompt: Option<String>,
+    pub ttl_secs: Option<u64>,
+    pub cache_control: Option<String>,
+    pub reference_materials: Option<bool>,
+}
+
+/// Limits on generating linked pages in the background, for content types with `prefetch_links` set
+#[derive(Debug, Deserialize, Clone)]
+pub struct PrefetchConfig {
+    /// Pages generated in the background at once
+    #[serde(default = "default_prefetch_concurrency")]
+    pub concurrency: usize,
+    /// Pages waiting to be prefetched, beyond which further links are dropped
+    #[serde(default = "default_prefetch_queue_size")]
+    pub queue_size: usize,
+    /// Spending on prefetched pages above which links are no longer prefetched
+    #[serde(default)]
+    pub daily: BudgetLimit,
+    #[serde(default)]
+    pub monthly: BudgetLimit,
+}
+
+impl Default for PrefetchConfig {
+    fn default() -> Self {
+        Self {
+            concurrency: default_prefetch_concurrency(),
+            queue_size: default_prefetch_queue_size(),
+            daily: BudgetLimit::default(),
+            monthly: BudgetLimit::default(),
+        }
     }
 }
 
-/// Root configuration structure
-#[derive(Debug, Deserialize)]
-pub struct WebSimConfig {
-    pub content_types: HashMap<String, ContentTypeConfig>,
+fn default_prefetch_concurrency() -> usize {
+    2
+}
+
+fn default_prefetch_queue_size() -> usize {
+    32
+}
+
+/// Spending caps that stop content generation once reached, leaving only cached content.
+/// Periods are calendar days and months in UTC.
+#[derive(Debug, Deserialize, Clone)]
+pub struct BudgetConfig {
+    #[serde(default)]
+    pub daily: BudgetLimit,
+    #[serde(default)]
+    pub monthly: BudgetLimit,
+    /// HTML served instead of generating content while a budget is exhausted
+    #[serde(default = "default_exhausted_page")]
+    pub exhausted_page: String,
+}
+
+/// Spending limit for a budget period, in total tokens and/or US dollars
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct BudgetLimit {
+    pub tokens: Option<u64>,
+    pub cost: Option<f64>,
+}
+
+fn default_exhausted_page() -> String {
+    "<h1>Budget exhausted</h1><p>This page hasn't been generated yet, and the spending budget has been reached. Only previously generated pages are available.</p>".to_string()
 }
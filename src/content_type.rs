@@ -1,21 +1,99 @@
 use crate::config::{ContentTypeConfig, WebSimConfig};
 
-/// Determines the content type based on the Accept header
-pub fn determine_from_accept<'a>(
+/// A media range from an `Accept` header, e.g. `image/*;q=0.8`
+#[derive(Debug, PartialEq)]
+struct MediaRange<'a> {
+    type_: &'a str,
+    subtype: &'a str,
+    /// Quality in thousandths, from 0 (not acceptable) to 1000
+    quality: u16,
+}
+
+impl MediaRange<'_> {
+    /// How specifically this range names `mime_type`: 2 for the type itself, 1 for `type/*` and 0 for `*/*`,
+    /// or `None` if it doesn't match
+    fn specificity(&self, mime_type: &str) -> Option<u8> {
+        let (type_, subtype) = mime_type.split_once('/')?;
+        match (self.type_, self.subtype) {
+            ("*", "*") => Some(0),
+            (t, "*") if t.eq_ignore_ascii_case(type_) => Some(1),
+            (t, s) if t.eq_ignore_ascii_case(type_) && s.eq_ignore_ascii_case(subtype) => Some(2),
+            _ => None,
+        }
+    }
+}
+
+/// Parses the media ranges of an `Accept` header (RFC 9110 section 12.5.1), skipping malformed ones.
+/// Parameters other than the `q` weight are ignored, as configured content types have none.
+fn parse_accept(header: &str) -> Vec<MediaRange<'_>> {
+    header
+        .split(',')
+        .filter_map(|range| {
+            let mut parts = range.split(';').map(str::trim);
+            let (type_, subtype) = parts.next()?.split_once('/')?;
+            if type_.is_empty() || subtype.is_empty() || (type_ == "*" && subtype != "*") {
+                return None;
+            }
+
+            let mut quality = 1000;
+            for param in parts {
+                if let Some((name, value)) = param.split_once('=')
+                    && name.trim().eq_ignore_ascii_case("q")
+                {
+                    let q: f32 = value.trim().parse().ok()?;
+                    if !(0.0..=1.0).contains(&q) {
+                        return None;
+                    }
+                    quality = (q * 1000.0).round() as u16;
+                    break;
+                }
+            }
+
+            Some(MediaRange {
+                type_,
+                subtype,
+                quality,
+            })
+        })
+        .collect()
+}
+
+/// Negotiates the content type for a request from its `Accept` header and path.
+/// Each configured type takes the quality of the most specific range matching it, and the type with the
+/// highest quality is chosen, preferring types named exactly over wildcard matches, then the type the path
+/// maps to, then config order. `*/*` only accepts the type the path maps to, so asset requests sent with
+/// wildcards aren't answered with another type. Without an `Accept` header the path decides.
+/// Returns `None` if no configured type is acceptable.
+pub fn negotiate<'a>(
     accept_header: Option<&str>,
+    path: &str,
     config: &'a WebSimConfig,
 ) -> Option<(&'a String, &'a ContentTypeConfig)> {
-    let accept = accept_header?;
-
-    // Check for specific content types in the Accept header
-    // Try to find a matching mime type in the config
-    for (mime_type, content_config) in &config.content_types {
-        if accept.contains(mime_type.as_str()) {
-            return Some((mime_type, content_config));
-        }
+    let from_path = determine_from_path(path, config);
+    let ranges = accept_header.map(parse_accept).unwrap_or_default();
+    if ranges.is_empty() {
+        return from_path;
     }
 
-    None
+    config
+        .content_types
+        .iter()
+        .enumerate()
+        .filter_map(|(index, (mime_type, content_config))| {
+            let (specificity, quality) = ranges
+                .iter()
+                .filter_map(|range| Some((range.specificity(mime_type)?, range.quality)))
+                .max_by_key(|(specificity, _)| *specificity)?;
+            let is_path_type = from_path.is_some_and(|(mime, _)| mime == mime_type);
+            if quality == 0 || (specificity == 0 && !is_path_type) {
+                return None;
+            }
+
+            let preference = (quality, specificity, is_path_type, std::cmp::Reverse(index));
+            Some((preference, (mime_type, content_config)))
+        })
+        .max_by_key(|(preference, _)| *preference)
+        .map(|(_, content_type)| content_type)
 }
 
 /// Determines the content type based on the request path
@@ -50,3 +128,121 @@ pub fn determine_from_path<'a>(
 
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "
+content_types:
+  text/html:
+    model: model
+    system_prompt: html
+    content_type_header: text/html
+    extensions: [html]
+  image/svg+xml:
+    model: model
+    system_prompt: svg
+    content_type_header: image/svg+xml
+    extensions: [svg]
+  application/json:
+    model: model
+    system_prompt: json
+    content_type_header: application/json
+    extensions: [json]
+  application/javascript:
+    model: model
+    system_prompt: js
+    content_type_header: application/javascript
+    extensions: [js]
+";
+
+    fn config() -> WebSimConfig {
+        ::config::Config::builder()
+            .add_source(::config::File::from_str(CONFIG, ::config::FileFormat::Yaml))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_parse_accept() {
+        let range = |type_, subtype, quality| MediaRange {
+            type_,
+            subtype,
+            quality,
+        };
+
+        assert_eq!(
+            parse_accept(
+                "text/html, application/xml;q=0.9, */* ; Q=0.8, text/plain;level=1;q=0.25"
+            ),
+            [
+                range("text", "html", 1000),
+                range("application", "xml", 900),
+                range("*", "*", 800),
+                range("text", "plain", 250)
+            ]
+        );
+        // Malformed ranges and out of range weights are skipped
+        assert_eq!(
+            parse_accept("text, */html, image/png;q=2, image/gif;q=x, , image/*"),
+            [range("image", "*", 1000)]
+        );
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let config = config();
+        let negotiate =
+            |accept, path| negotiate(accept, path, &config).map(|(mime, _)| mime.as_str());
+
+        // Chrome and Firefox navigations
+        let navigation = "text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,image/apng,*/*;q=0.8,application/signed-exchange;v=b3;q=0.7";
+        assert_eq!(negotiate(Some(navigation), "/fruits"), Some("text/html"));
+        assert_eq!(negotiate(Some(navigation), "/logo.svg"), Some("text/html"));
+        let navigation = "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8";
+        assert_eq!(negotiate(Some(navigation), "/data.json"), Some("text/html"));
+
+        // Chrome and Firefox images, including for paths without an extension
+        let image = "image/avif,image/webp,image/apng,image/svg+xml,image/*,*/*;q=0.8";
+        assert_eq!(negotiate(Some(image), "/logo.svg"), Some("image/svg+xml"));
+        assert_eq!(negotiate(Some(image), "/banner"), Some("image/svg+xml"));
+        let image = "image/avif,image/webp,image/png,image/svg+xml,image/*;q=0.8,*/*;q=0.5";
+        assert_eq!(negotiate(Some(image), "/logo.svg"), Some("image/svg+xml"));
+
+        // Scripts and `fetch()` send `*/*`, which accepts the type the path maps to
+        assert_eq!(
+            negotiate(Some("*/*"), "/app.js"),
+            Some("application/javascript")
+        );
+        assert_eq!(negotiate(Some("*/*"), "/api/apples"), Some("text/html"));
+        assert_eq!(negotiate(Some("text/css,*/*;q=0.1"), "/style.css"), None);
+        assert_eq!(negotiate(None, "/app.js"), Some("application/javascript"));
+        assert_eq!(negotiate(None, "/style.css"), None);
+
+        // Weights decide over order, and explicitly unacceptable types are never chosen
+        assert_eq!(
+            negotiate(Some("text/html;q=0.1, application/json"), "/fruits"),
+            Some("application/json")
+        );
+        assert_eq!(negotiate(Some("text/html;q=0, */*"), "/fruits"), None);
+        assert_eq!(negotiate(Some("application/xml"), "/fruits"), None);
+        assert_eq!(negotiate(Some("IMAGE/*"), "/fruits"), Some("image/svg+xml"));
+
+        // Ties go to the type named exactly, then the path's type, then config order
+        assert_eq!(
+            negotiate(Some("image/*, application/json"), "/logo.svg"),
+            Some("application/json")
+        );
+        assert_eq!(
+            negotiate(Some("application/json, image/svg+xml"), "/data.json"),
+            Some("application/json")
+        );
+        assert_eq!(
+            negotiate(Some("application/json, image/svg+xml"), "/fruits"),
+            Some("image/svg+xml")
+        );
+    }
+}
@@ -0,0 +1,173 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Result, bail};
+use axum::Router;
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::header;
+use futures_util::{StreamExt, stream};
+use tower::ServiceExt;
+use tracing::info;
+
+use crate::config::WebSimConfig;
+use crate::links::{self, Link, LinkKind};
+use crate::server::{BackendMode, build_app};
+use crate::utils::normalize_path;
+
+/// Limits on a crawl
+#[derive(Debug, Clone)]
+pub struct CrawlOptions {
+    /// Number of links followed away from the seed page
+    pub depth: usize,
+    /// Most pages requested in one run, so at most this many generations
+    pub max_pages: usize,
+    /// Pages requested at once
+    pub concurrency: usize,
+    /// Name of the configured site to crawl, instead of the top-level site
+    pub site: Option<String>,
+}
+
+/// A page requested during a crawl
+#[derive(Debug)]
+pub struct CrawledPage {
+    pub path_and_query: String,
+    /// Links followed from the seed page to reach this page
+    pub depth: usize,
+    pub status: u16,
+    pub content_type: Option<String>,
+    pub bytes: usize,
+}
+
+/// Requests `seed` and then the pages and assets it references, level by level, through the same
+/// pipeline as the server, so anything not already stored is generated into the database.
+/// Links are taken from HTML pages and `fetch()` calls in scripts, and each page is requested once.
+pub async fn crawl(
+    db_path: PathBuf,
+    config_path: PathBuf,
+    mode: BackendMode,
+    seed: &str,
+    options: &CrawlOptions,
+) -> Result<Vec<CrawledPage>> {
+    if !seed.starts_with('/') {
+        bail!("Seed must be a path starting with /: {}", seed);
+    }
+    let (host, seed) = match &options.site {
+        Some(site) => site_entry(&config_path, site, seed)?,
+        None => (None, seed.to_string()),
+    };
+    let app = build_app(Some(db_path), config_path, mode).await?;
+
+    let seed = Link {
+        path_and_query: normalize_path(&seed).to_string(),
+        kind: LinkKind::Navigation,
+    };
+    let mut seen = HashSet::from([seed.path_and_query.clone()]);
+    let mut frontier = vec![seed];
+    let mut crawled = Vec::new();
+
+    for depth in 0..=options.depth {
+        frontier.truncate(options.max_pages - crawled.len());
+        if frontier.is_empty() {
+            break;
+        }
+
+        let results: Vec<Result<(CrawledPage, Vec<Link>)>> = stream::iter(frontier)
+            .map(|link| fetch(app.clone(), host.as_deref(), link, depth))
+            .buffered(options.concurrency.max(1))
+            .collect()
+            .await;
+
+        frontier = Vec::new();
+        for result in results {
+            let (page, links) = result?;
+            info!(
+                status = page.status,
+                depth = page.depth,
+                bytes = page.bytes,
+                links = links.len(),
+                "Crawled {}",
+                page.path_and_query
+            );
+            crawled.push(page);
+            frontier.extend(
+                links
+                    .into_iter()
+                    .filter(|link| seen.insert(link.path_and_query.clone())),
+            );
+        }
+    }
+
+    Ok(crawled)
+}
+
+/// The `Host` header and seed that reach a configured site: its first host, or else the seed under its path prefix
+fn site_entry(config_path: &Path, site: &str, seed: &str) -> Result<(Option<String>, String)> {
+    let config = WebSimConfig::load(config_path)?;
+    let Some(site_config) = config.sites.get(site) else {
+        bail!("Unknown site: {}", site);
+    };
+    if let Some(host) = site_config.hosts.first() {
+        return Ok((Some(host.clone()), seed.to_string()));
+    }
+
+    let prefix = site_config
+        .path_prefix
+        .as_deref()
+        .unwrap_or_default()
+        .trim_end_matches('/');
+    let under_prefix = seed
+        .strip_prefix(prefix)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'));
+    if under_prefix {
+        Ok((None, seed.to_string()))
+    } else {
+        Ok((None, format!("{}{}", prefix, seed)))
+    }
+}
+
+/// Requests a page, sent to `host` if set, and returns the links to follow from it
+async fn fetch(
+    app: Router,
+    host: Option<&str>,
+    link: Link,
+    depth: usize,
+) -> Result<(CrawledPage, Vec<Link>)> {
+    let mut request = Request::get(&link.path_and_query);
+    if let Some(accept) = link.accept() {
+        request = request.header(header::ACCEPT, accept);
+    }
+    if let Some(host) = host {
+        request = request.header(header::HOST, host);
+    }
+    let response = app.oneshot(request.body(Body::empty())?).await?;
+
+    let status = response.status();
+    let content_type = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await?;
+
+    let follow = status.is_success()
+        && content_type
+            .as_deref()
+            .is_some_and(|ct| ct.starts_with("text/html") || ct.contains("javascript"));
+    let links = if follow {
+        links::extract(&String::from_utf8_lossy(&body), &link.path_and_query)
+    } else {
+        Vec::new()
+    };
+
+    Ok((
+        CrawledPage {
+            path_and_query: link.path_and_query,
+            depth,
+            status: status.as_u16(),
+            content_type,
+            bytes: body.len(),
+        },
+        links,
+    ))
+}
@@ -1,19 +1,338 @@
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use anyhow::Result;
-use rusqlite::{Connection, params};
+use anyhow::{Result, bail};
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::Serialize;
 use tracing::info;
 
-/// Database wrapper for storing content
+use crate::backend::Usage;
+use crate::budget::Spending;
+use crate::facts::Fact;
+use crate::usage::UsageTotals;
+
+/// Schema migrations, applied in order to bring a database up to date.
+/// The number applied so far is tracked in `PRAGMA user_version`.
+const MIGRATIONS: &[&str] = &[
+    // 1: content and usage tables, created without a version by earlier releases
+    "CREATE TABLE IF NOT EXISTS resources (
+        path TEXT NOT NULL,
+        query TEXT NOT NULL,
+        content TEXT NOT NULL,
+        PRIMARY KEY (path, query)
+    );
+    CREATE TABLE IF NOT EXISTS usage (
+        id INTEGER PRIMARY KEY,
+        path TEXT NOT NULL,
+        query TEXT NOT NULL,
+        model TEXT NOT NULL,
+        content_type TEXT NOT NULL,
+        prompt_tokens INTEGER NOT NULL,
+        completion_tokens INTEGER NOT NULL,
+        cost REAL,
+        created_at INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS usage_created_at ON usage (created_at);",
+    // 2: resource metadata, left empty for resources generated before it was recorded
+    "ALTER TABLE resources ADD COLUMN mime_type TEXT;
+    ALTER TABLE resources ADD COLUMN model TEXT;
+    ALTER TABLE resources ADD COLUMN system_prompt_hash TEXT;
+    ALTER TABLE resources ADD COLUMN method TEXT;
+    ALTER TABLE resources ADD COLUMN status INTEGER;
+    ALTER TABLE resources ADD COLUMN duration_ms INTEGER;
+    ALTER TABLE resources ADD COLUMN created_at INTEGER;
+    ALTER TABLE resources ADD COLUMN updated_at INTEGER;",
+    // 3: key resources by MIME type as well, so each negotiated representation of a path is cached separately.
+    // Resources stored before the MIME type was recorded get a best guess from the path's extension.
+    "CREATE TABLE resources_new (
+        path TEXT NOT NULL,
+        query TEXT NOT NULL,
+        mime_type TEXT NOT NULL,
+        content TEXT NOT NULL,
+        model TEXT,
+        system_prompt_hash TEXT,
+        method TEXT,
+        status INTEGER,
+        duration_ms INTEGER,
+        created_at INTEGER,
+        updated_at INTEGER,
+        PRIMARY KEY (path, query, mime_type)
+    );
+    INSERT INTO resources_new (path, query, mime_type, content, model, system_prompt_hash, method,
+        status, duration_ms, created_at, updated_at)
+    SELECT path, query,
+        COALESCE(mime_type, CASE
+            WHEN lower(path) LIKE '%.svg' THEN 'image/svg+xml'
+            WHEN lower(path) LIKE '%.json' THEN 'application/json'
+            WHEN lower(path) LIKE '%.js' THEN 'application/javascript'
+            WHEN lower(path) LIKE '%.css' THEN 'text/css'
+            ELSE 'text/html'
+        END),
+        content, model, system_prompt_hash, method, status, duration_ms, created_at, updated_at
+    FROM resources;
+    DROP TABLE resources;
+    ALTER TABLE resources_new RENAME TO resources;",
+    // 4: keep every generation as a numbered version. `resources` holds the version being served,
+    // which is the latest unless it has been pinned or rolled back.
+    "CREATE TABLE resource_versions (
+        path TEXT NOT NULL,
+        query TEXT NOT NULL,
+        mime_type TEXT NOT NULL,
+        version INTEGER NOT NULL,
+        content TEXT NOT NULL,
+        model TEXT,
+        system_prompt_hash TEXT,
+        method TEXT,
+        status INTEGER,
+        duration_ms INTEGER,
+        created_at INTEGER,
+        PRIMARY KEY (path, query, mime_type, version)
+    );
+    INSERT INTO resource_versions (path, query, mime_type, version, content, model, system_prompt_hash,
+        method, status, duration_ms, created_at)
+    SELECT path, query, mime_type, 1, content, model, system_prompt_hash, method, status, duration_ms,
+        COALESCE(updated_at, created_at)
+    FROM resources;
+    ALTER TABLE resources ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+    ALTER TABLE resources ADD COLUMN pinned INTEGER NOT NULL DEFAULT 0;",
+    // 5: when each resource was last served, for least recently used eviction
+    "ALTER TABLE resources ADD COLUMN accessed_at INTEGER;
+    UPDATE resources SET accessed_at = updated_at;",
+    // 6: mark hand-written content imported from files, which is never regenerated or evicted
+    "ALTER TABLE resources ADD COLUMN authored INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE resource_versions ADD COLUMN authored INTEGER NOT NULL DEFAULT 0;",
+    // 7: mark usage of background prefetches, which have their own spending cap
+    "ALTER TABLE usage ADD COLUMN speculative INTEGER NOT NULL DEFAULT 0;",
+    // 8: key resources by site as well, so each site served has its own namespace.
+    // Existing resources belong to the default site, named ''.
+    "CREATE TABLE resources_new (
+        site TEXT NOT NULL DEFAULT '',
+        path TEXT NOT NULL,
+        query TEXT NOT NULL,
+        mime_type TEXT NOT NULL,
+        content TEXT NOT NULL,
+        model TEXT,
+        system_prompt_hash TEXT,
+        method TEXT,
+        status INTEGER,
+        duration_ms INTEGER,
+        created_at INTEGER,
+        updated_at INTEGER,
+        version INTEGER NOT NULL DEFAULT 1,
+        pinned INTEGER NOT NULL DEFAULT 0,
+        accessed_at INTEGER,
+        authored INTEGER NOT NULL DEFAULT 0,
+        PRIMARY KEY (site, path, query, mime_type)
+    );
+    INSERT INTO resources_new (path, query, mime_type, content, model, system_prompt_hash, method, status,
+        duration_ms, created_at, updated_at, version, pinned, accessed_at, authored)
+    SELECT path, query, mime_type, content, model, system_prompt_hash, method, status, duration_ms,
+        created_at, updated_at, version, pinned, accessed_at, authored
+    FROM resources;
+    DROP TABLE resources;
+    ALTER TABLE resources_new RENAME TO resources;
+    CREATE TABLE resource_versions_new (
+        site TEXT NOT NULL DEFAULT '',
+        path TEXT NOT NULL,
+        query TEXT NOT NULL,
+        mime_type TEXT NOT NULL,
+        version INTEGER NOT NULL,
+        content TEXT NOT NULL,
+        model TEXT,
+        system_prompt_hash TEXT,
+        method TEXT,
+        status INTEGER,
+        duration_ms INTEGER,
+        created_at INTEGER,
+        authored INTEGER NOT NULL DEFAULT 0,
+        PRIMARY KEY (site, path, query, mime_type, version)
+    );
+    INSERT INTO resource_versions_new (path, query, mime_type, version, content, model, system_prompt_hash,
+        method, status, duration_ms, created_at, authored)
+    SELECT path, query, mime_type, version, content, model, system_prompt_hash, method, status, duration_ms,
+        created_at, authored
+    FROM resource_versions;
+    DROP TABLE resource_versions;
+    ALTER TABLE resource_versions_new RENAME TO resource_versions;",
+    // 9: facts generated pages established about a site's world, given to the model for later pages
+    "CREATE TABLE facts (
+        site TEXT NOT NULL,
+        name TEXT NOT NULL,
+        statement TEXT NOT NULL,
+        path TEXT NOT NULL,
+        created_at INTEGER NOT NULL,
+        PRIMARY KEY (site, name)
+    );",
+    // 10: record the site each generation was for, so usage can be reported per site.
+    // Existing usage belongs to the default site.
+    "ALTER TABLE usage ADD COLUMN site TEXT NOT NULL DEFAULT '';",
+];
+
+/// Applies any migrations the database hasn't had yet, each in its own transaction
+fn migrate(conn: &mut Connection) -> Result<()> {
+    let version: usize = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    if version > MIGRATIONS.len() {
+        bail!(
+            "Database schema version {} is newer than this release supports ({})",
+            version,
+            MIGRATIONS.len()
+        );
+    }
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", index + 1)?;
+        tx.commit()?;
+        info!("Migrated database schema to version {}", index + 1);
+    }
+
+    Ok(())
+}
+
+/// How content was generated, stored as resource metadata and recorded for cost accounting
+#[derive(Debug, Clone)]
+pub struct Generation {
+    pub model: String,
+    /// MIME type of the generated content
+    pub mime_type: String,
+    /// SHA-256 hex digest of the system prompt
+    pub system_prompt_hash: String,
+    /// HTTP method of the request that triggered the generation
+    pub method: String,
+    /// HTTP status the generated content was served with
+    pub status: u16,
+    pub duration: Duration,
+    pub usage: Usage,
+    /// Whether the content was prefetched in the background rather than requested
+    pub speculative: bool,
+}
+
+/// Content served from the cache
+#[derive(Debug, Clone, PartialEq)]
+pub struct CachedContent {
+    pub content: String,
+    /// When the served content last changed, unknown for resources stored before it was recorded
+    pub last_modified: Option<SystemTime>,
+    /// Whether the content was imported rather than generated
+    pub authored: bool,
+}
+
+/// A resource as currently served
+#[derive(Debug, Clone)]
+pub struct Resource {
+    pub path: String,
+    pub query: String,
+    pub mime_type: String,
+    pub content: String,
+    pub version: u32,
+    pub model: Option<String>,
+    /// Seconds since the Unix epoch
+    pub updated_at: Option<i64>,
+}
+
+/// A stored version of a resource
+#[derive(Debug, Clone, Serialize)]
+pub struct VersionInfo {
+    pub version: u32,
+    pub model: Option<String>,
+    /// Seconds since the Unix epoch
+    pub created_at: Option<i64>,
+    pub duration_ms: Option<i64>,
+    pub bytes: usize,
+    /// Whether this is the version being served
+    pub current: bool,
+    /// Whether this version is served even after the resource is regenerated
+    pub pinned: bool,
+    /// Whether this version was imported rather than generated
+    pub authored: bool,
+}
+
+/// Selects stored resources to invalidate. Set filters are combined, and a filter with
+/// nothing set matches every resource.
+#[derive(Debug, Clone, Default)]
+pub struct PurgeFilter {
+    /// Exact path, with every query variation unless `query` is also set
+    pub path: Option<String>,
+    pub query: Option<String>,
+    /// Path prefix matching whole segments, e.g. `/blog` matches `/blog` and `/blog/post` but not `/blogs`
+    pub prefix: Option<String>,
+    /// Model the resource was generated with
+    pub model: Option<String>,
+    pub mime_type: Option<String>,
+}
+
+impl PurgeFilter {
+    /// Whether no filter is set, matching every resource
+    pub fn is_empty(&self) -> bool {
+        self.path.is_none()
+            && self.query.is_none()
+            && self.prefix.is_none()
+            && self.model.is_none()
+            && self.mime_type.is_none()
+    }
+
+    /// Builds the SQL condition and parameters matching this filter
+    fn to_sql(&self) -> (String, Vec<String>) {
+        let mut conditions = Vec::new();
+        let mut params = Vec::new();
+        let mut add = |condition: &str, value: &str| {
+            params.push(value.to_string());
+            conditions.push(condition.replace('?', &format!("?{}", params.len())));
+        };
+
+        if let Some(path) = &self.path {
+            add("path = ?", path);
+        }
+        if let Some(query) = &self.query {
+            add("query = ?", query);
+        }
+        if let Some(prefix) = &self.prefix {
+            let prefix = prefix.trim_end_matches('/');
+            if !prefix.is_empty() {
+                add(
+                    "(path = ? OR substr(path, 1, length(?) + 1) = ? || '/')",
+                    prefix,
+                );
+            }
+        }
+        if let Some(model) = &self.model {
+            add("model = ?", model);
+        }
+        if let Some(mime_type) = &self.mime_type {
+            add("mime_type = ?", mime_type);
+        }
+
+        if conditions.is_empty() {
+            ("1".to_string(), params)
+        } else {
+            (conditions.join(" AND "), params)
+        }
+    }
+}
+
+/// Usage totals for a single model, content type and path
+#[derive(Debug, Clone)]
+pub struct UsageSummary {
+    pub model: String,
+    pub content_type: String,
+    pub path: String,
+    pub totals: UsageTotals,
+}
+
+/// Database wrapper for storing content, scoped to the resources of one site.
+/// Usage is recorded for the site but summed across sites for spending, so budgets cover everything a server generates.
 pub struct Database {
     conn: Arc<Mutex<Connection>>,
+    /// Site whose resources are read and written, `""` for the default site
+    site: String,
 }
 
 impl Database {
     /// Create a new database (in-memory or file-based)
     pub fn new(db_path: Option<PathBuf>) -> Result<Self> {
-        let conn = if let Some(path) = db_path {
+        let mut conn = if let Some(path) = db_path {
             info!("Opening SQLite database at: {}", path.display());
             Connection::open(path)?
         } else {
@@ -21,35 +340,101 @@ impl Database {
             Connection::open_in_memory()?
         };
 
-        // Create the resources table if it doesn't exist
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS resources (
-                path TEXT NOT NULL,
-                query TEXT NOT NULL,
-                content TEXT NOT NULL,
-                PRIMARY KEY (path, query)
-            )",
-            [],
-        )?;
+        migrate(&mut conn)?;
 
         Ok(Self {
             conn: Arc::new(Mutex::new(conn)),
+            site: String::new(),
         })
     }
 
-    /// Look up content by path and query
-    pub async fn get(&self, path: &str, query: &str) -> Result<Option<String>> {
+    /// Handle on the same database scoped to the resources of `site`
+    pub fn for_site(&self, site: &str) -> Self {
+        Self {
+            conn: Arc::clone(&self.conn),
+            site: site.to_string(),
+        }
+    }
+
+    /// Site whose resources are read and written, `""` for the default site
+    pub fn site(&self) -> &str {
+        &self.site
+    }
+
+    /// Look up content by path, query and MIME type, recording the access for least recently used eviction.
+    /// Generated content older than `ttl` is treated as missing, unless pinned.
+    pub async fn get(
+        &self,
+        path: &str,
+        query: &str,
+        mime_type: &str,
+        ttl: Option<Duration>,
+    ) -> Result<Option<CachedContent>> {
         let conn = Arc::clone(&self.conn);
+        let site = self.site.clone();
         let path = path.to_string();
         let query = query.to_string();
+        let mime_type = mime_type.to_string();
+        let ttl_secs = ttl.map(|ttl| ttl.as_secs() as i64);
 
         tokio::task::Builder::new()
             .name("db-get")
+            .spawn_blocking(move || {
+                let conn = conn.lock().unwrap();
+                let now = unix_time()?;
+                let cached = conn
+                    .query_row(
+                        "SELECT content, updated_at, authored FROM resources
+                        WHERE site = ?6 AND path = ?1 AND query = ?2 AND mime_type = ?3
+                            AND (?4 IS NULL OR authored OR pinned OR COALESCE(updated_at, 0) > ?5 - ?4)",
+                        params![path, query, mime_type, ttl_secs, now, site],
+                        |row| {
+                            Ok(CachedContent {
+                                content: row.get(0)?,
+                                last_modified: row
+                                    .get::<_, Option<i64>>(1)?
+                                    .map(|secs| UNIX_EPOCH + Duration::from_secs(secs as u64)),
+                                authored: row.get(2)?,
+                            })
+                        },
+                    )
+                    .optional()?;
+
+                if cached.is_some() {
+                    conn.execute(
+                        "UPDATE resources SET accessed_at = ?4
+                        WHERE site = ?5 AND path = ?1 AND query = ?2 AND mime_type = ?3",
+                        params![path, query, mime_type, now, site],
+                    )?;
+                }
+                Ok(cached)
+            })?
+            .await?
+    }
+
+    /// Look up content by path and query to use as reference material, preferring the given MIME type
+    /// and otherwise taking the most recently generated representation
+    pub async fn get_reference(
+        &self,
+        path: &str,
+        query: &str,
+        preferred_mime_type: &str,
+    ) -> Result<Option<String>> {
+        let conn = Arc::clone(&self.conn);
+        let site = self.site.clone();
+        let path = path.to_string();
+        let query = query.to_string();
+        let mime_type = preferred_mime_type.to_string();
+
+        tokio::task::Builder::new()
+            .name("db-get-reference")
             .spawn_blocking(move || {
                 let conn = conn.lock().unwrap();
                 let result: Result<String, rusqlite::Error> = conn.query_row(
-                    "SELECT content FROM resources WHERE path = ?1 AND query = ?2",
-                    params![path, query],
+                    "SELECT content FROM resources WHERE site = ?4 AND path = ?1 AND query = ?2
+                    ORDER BY mime_type = ?3 DESC, updated_at DESC
+                    LIMIT 1",
+                    params![path, query, mime_type, site],
                     |row| row.get(0),
                 );
 
@@ -62,23 +447,1235 @@ impl Database {
             .await?
     }
 
-    /// Store content in database
-    pub async fn set(&self, path: &str, query: &str, content: &str) -> Result<()> {
+    /// List every resource being served, ordered by path, query and MIME type
+    pub async fn resources(&self) -> Result<Vec<Resource>> {
+        let conn = Arc::clone(&self.conn);
+        let site = self.site.clone();
+
+        tokio::task::Builder::new()
+            .name("db-resources")
+            .spawn_blocking(move || {
+                let conn = conn.lock().unwrap();
+                let mut stmt = conn.prepare(
+                    "SELECT path, query, mime_type, content, version, model, updated_at
+                    FROM resources
+                    WHERE site = ?1
+                    ORDER BY path, query, mime_type",
+                )?;
+                let rows = stmt.query_map(params![site], |row| {
+                    Ok(Resource {
+                        path: row.get(0)?,
+                        query: row.get(1)?,
+                        mime_type: row.get(2)?,
+                        content: row.get(3)?,
+                        version: row.get(4)?,
+                        model: row.get(5)?,
+                        updated_at: row.get(6)?,
+                    })
+                })?;
+                Ok(rows.collect::<Result<Vec<_>, _>>()?)
+            })?
+            .await?
+    }
+
+    /// Look up a specific version of a resource
+    pub async fn get_version(
+        &self,
+        path: &str,
+        query: &str,
+        mime_type: &str,
+        version: u32,
+    ) -> Result<Option<String>> {
+        let conn = Arc::clone(&self.conn);
+        let site = self.site.clone();
+        let path = path.to_string();
+        let query = query.to_string();
+        let mime_type = mime_type.to_string();
+
+        tokio::task::Builder::new()
+            .name("db-get-version")
+            .spawn_blocking(move || {
+                let conn = conn.lock().unwrap();
+                Ok(conn
+                    .query_row(
+                        "SELECT content FROM resource_versions
+                        WHERE site = ?5 AND path = ?1 AND query = ?2 AND mime_type = ?3 AND version = ?4",
+                        params![path, query, mime_type, version, site],
+                        |row| row.get(0),
+                    )
+                    .optional()?)
+            })?
+            .await?
+    }
+
+    /// List every stored version of a resource, oldest first
+    pub async fn versions(
+        &self,
+        path: &str,
+        query: &str,
+        mime_type: &str,
+    ) -> Result<Vec<VersionInfo>> {
+        let conn = Arc::clone(&self.conn);
+        let site = self.site.clone();
+        let path = path.to_string();
+        let query = query.to_string();
+        let mime_type = mime_type.to_string();
+
+        tokio::task::Builder::new()
+            .name("db-versions")
+            .spawn_blocking(move || {
+                let conn = conn.lock().unwrap();
+                let mut stmt = conn.prepare(
+                    "SELECT v.version, v.model, v.created_at, v.duration_ms, length(CAST(v.content AS BLOB)),
+                        r.version IS v.version, r.version IS v.version AND r.pinned, v.authored
+                    FROM resource_versions v
+                    LEFT JOIN resources r USING (site, path, query, mime_type)
+                    WHERE v.site = ?4 AND v.path = ?1 AND v.query = ?2 AND v.mime_type = ?3
+                    ORDER BY v.version",
+                )?;
+                let rows = stmt.query_map(params![path, query, mime_type, site], |row| {
+                    Ok(VersionInfo {
+                        version: row.get(0)?,
+                        model: row.get(1)?,
+                        created_at: row.get(2)?,
+                        duration_ms: row.get(3)?,
+                        bytes: row.get(4)?,
+                        current: row.get(5)?,
+                        pinned: row.get(6)?,
+                        authored: row.get(7)?,
+                    })
+                })?;
+                Ok(rows.collect::<Result<Vec<_>, _>>()?)
+            })?
+            .await?
+    }
+
+    /// Serve `version` of a resource even after it is regenerated.
+    /// Returns false if there is no such version.
+    pub async fn pin(
+        &self,
+        path: &str,
+        query: &str,
+        mime_type: &str,
+        version: u32,
+    ) -> Result<bool> {
+        self.set_current(path, query, mime_type, Some(version), true)
+            .await
+    }
+
+    /// Serve `version` of a resource until it is next regenerated.
+    /// Returns false if there is no such version.
+    pub async fn rollback(
+        &self,
+        path: &str,
+        query: &str,
+        mime_type: &str,
+        version: u32,
+    ) -> Result<bool> {
+        self.set_current(path, query, mime_type, Some(version), false)
+            .await
+    }
+
+    /// Go back to serving the latest version of a resource.
+    /// Returns false if the resource has no versions.
+    pub async fn unpin(&self, path: &str, query: &str, mime_type: &str) -> Result<bool> {
+        self.set_current(path, query, mime_type, None, false).await
+    }
+
+    /// Makes a version of a resource, or the latest if `None`, the one being served
+    async fn set_current(
+        &self,
+        path: &str,
+        query: &str,
+        mime_type: &str,
+        version: Option<u32>,
+        pinned: bool,
+    ) -> Result<bool> {
+        let conn = Arc::clone(&self.conn);
+        let site = self.site.clone();
+        let path = path.to_string();
+        let query = query.to_string();
+        let mime_type = mime_type.to_string();
+
+        tokio::task::Builder::new()
+            .name("db-set-current")
+            .spawn_blocking(move || {
+                let conn = conn.lock().unwrap();
+                let updated = conn.execute(
+                    "INSERT INTO resources (site, path, query, mime_type, content, model, system_prompt_hash,
+                        method, status, duration_ms, created_at, updated_at, version, pinned, authored)
+                    SELECT site, path, query, mime_type, content, model, system_prompt_hash, method, status,
+                        duration_ms, created_at, ?5, version, ?6, authored
+                    FROM resource_versions
+                    WHERE site = ?7 AND path = ?1 AND query = ?2 AND mime_type = ?3
+                        AND version = COALESCE(?4, (
+                            SELECT MAX(version) FROM resource_versions
+                            WHERE site = ?7 AND path = ?1 AND query = ?2 AND mime_type = ?3
+                        ))
+                    ON CONFLICT (site, path, query, mime_type) DO UPDATE SET
+                        content = excluded.content,
+                        model = excluded.model,
+                        system_prompt_hash = excluded.system_prompt_hash,
+                        method = excluded.method,
+                        status = excluded.status,
+                        duration_ms = excluded.duration_ms,
+                        updated_at = excluded.updated_at,
+                        version = excluded.version,
+                        pinned = excluded.pinned,
+                        authored = excluded.authored",
+                    params![path, query, mime_type, version, unix_time()?, pinned, site],
+                )?;
+                Ok(updated > 0)
+            })?
+            .await?
+    }
+
+    /// Invalidate stored resources so they are regenerated on their next request. Imported content is kept,
+    /// as are the versions of invalidated resources, so they can still be viewed or rolled back to.
+    /// Returns the number of resources invalidated.
+    pub async fn purge(&self, filter: &PurgeFilter) -> Result<usize> {
+        let conn = Arc::clone(&self.conn);
+        let site = self.site.clone();
+        let (condition, mut params) = filter.to_sql();
+
+        tokio::task::Builder::new()
+            .name("db-purge")
+            .spawn_blocking(move || {
+                let conn = conn.lock().unwrap();
+                params.push(site);
+                let purged = conn.execute(
+                    &format!(
+                        "DELETE FROM resources WHERE site = ?{} AND NOT authored AND {}",
+                        params.len(),
+                        condition
+                    ),
+                    rusqlite::params_from_iter(params),
+                )?;
+                Ok(purged)
+            })?
+            .await?
+    }
+
+    /// Remove resources that were generated longer ago than their TTL, unless pinned or imported.
+    /// `ttl` gives the TTL of a resource from its path and MIME type, `None` if it doesn't expire.
+    /// Their versions are kept. Returns the number of resources removed.
+    pub async fn evict_expired<F>(&self, ttl: F) -> Result<usize>
+    where
+        F: Fn(&str, &str) -> Option<Duration>,
+    {
+        let conn = Arc::clone(&self.conn);
+        let site = self.site.clone();
+
+        let (now, candidates) = tokio::task::Builder::new()
+            .name("db-expiry-candidates")
+            .spawn_blocking(move || -> Result<_> {
+                let conn = conn.lock().unwrap();
+                let mut stmt = conn.prepare(
+                    "SELECT path, query, mime_type, updated_at FROM resources
+                    WHERE site = ?1 AND NOT pinned AND NOT authored",
+                )?;
+                let rows = stmt.query_map(params![site], |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, String>(2)?,
+                        row.get::<_, Option<i64>>(3)?,
+                    ))
+                })?;
+                Ok((unix_time()?, rows.collect::<Result<Vec<_>, _>>()?))
+            })?
+            .await??;
+
+        let expired: Vec<_> = candidates
+            .into_iter()
+            .filter(|(path, _, mime_type, updated_at)| {
+                ttl(path, mime_type)
+                    .is_some_and(|ttl| updated_at.unwrap_or(0) <= now - ttl.as_secs() as i64)
+            })
+            .collect();
+        if expired.is_empty() {
+            return Ok(0);
+        }
+
+        let conn = Arc::clone(&self.conn);
+        let site = self.site.clone();
+
+        tokio::task::Builder::new()
+            .name("db-evict-expired")
+            .spawn_blocking(move || {
+                let mut conn = conn.lock().unwrap();
+                let tx = conn.transaction()?;
+                let mut evicted = 0;
+                // Resources regenerated since they were listed no longer match their old `updated_at`
+                for (path, query, mime_type, updated_at) in expired {
+                    evicted += tx.execute(
+                        "DELETE FROM resources
+                        WHERE site = ?5 AND path = ?1 AND query = ?2 AND mime_type = ?3
+                            AND updated_at IS ?4 AND NOT pinned AND NOT authored",
+                        params![path, query, mime_type, updated_at, site],
+                    )?;
+                }
+                tx.commit()?;
+                Ok(evicted)
+            })?
+            .await?
+    }
+
+    /// Remove the least recently served resources until the content of those left totals at most
+    /// `max_bytes`. Pinned and imported resources count towards the total but are never removed, and versions
+    /// are kept until [`Database::evict_versions`] removes them.
+    /// Returns the number of resources removed.
+    pub async fn evict_least_recently_used(&self, max_bytes: u64) -> Result<usize> {
         let conn = Arc::clone(&self.conn);
+        let site = self.site.clone();
+
+        tokio::task::Builder::new()
+            .name("db-evict-lru")
+            .spawn_blocking(move || {
+                let conn = conn.lock().unwrap();
+                let evicted = conn.execute(
+                    "DELETE FROM resources WHERE rowid IN (
+                        SELECT rowid FROM (
+                            SELECT rowid, SUM(length(CAST(content AS BLOB))) OVER (
+                                ORDER BY COALESCE(accessed_at, updated_at, 0) DESC, rowid DESC
+                            ) AS kept_bytes
+                            FROM resources
+                            WHERE site = ?2 AND NOT pinned AND NOT authored
+                        )
+                        WHERE kept_bytes > ?1 - (
+                            SELECT TOTAL(length(CAST(content AS BLOB))) FROM resources
+                            WHERE site = ?2 AND (pinned OR authored)
+                        )
+                    )",
+                    params![max_bytes as i64, site],
+                )?;
+                Ok(evicted)
+            })?
+            .await?
+    }
+
+    /// Remove the oldest versions until the content of those left totals at most `max_bytes`. Versions being
+    /// served, which includes pinned ones, and imported versions count towards the total but are never removed.
+    /// Returns the number of versions removed.
+    pub async fn evict_versions(&self, max_bytes: u64) -> Result<usize> {
+        let conn = Arc::clone(&self.conn);
+        let site = self.site.clone();
+
+        tokio::task::Builder::new()
+            .name("db-evict-versions")
+            .spawn_blocking(move || {
+                let conn = conn.lock().unwrap();
+                let evicted = conn.execute(
+                    "WITH kept AS (
+                        SELECT v.rowid, v.authored OR EXISTS (
+                            SELECT 1 FROM resources r
+                            WHERE r.site = v.site AND r.path = v.path AND r.query = v.query
+                                AND r.mime_type = v.mime_type AND r.version = v.version
+                        ) AS kept, length(CAST(v.content AS BLOB)) AS bytes, v.created_at
+                        FROM resource_versions v
+                        WHERE v.site = ?2
+                    )
+                    DELETE FROM resource_versions WHERE rowid IN (
+                        SELECT rowid FROM (
+                            SELECT rowid, SUM(bytes) OVER (
+                                ORDER BY COALESCE(created_at, 0) DESC, rowid DESC
+                            ) AS kept_bytes
+                            FROM kept
+                            WHERE NOT kept
+                        )
+                        WHERE kept_bytes > ?1 - (SELECT TOTAL(bytes) FROM kept WHERE kept)
+                    )",
+                    params![max_bytes as i64, site],
+                )?;
+                Ok(evicted)
+            })?
+            .await?
+    }
+
+    /// Store content in database as a new version, along with the metadata and usage of the generation
+    /// that produced it. The new version is served unless an earlier version is pinned or was imported.
+    /// Returns when the content was stored, or `None` if an earlier version is still served.
+    pub async fn set(
+        &self,
+        path: &str,
+        query: &str,
+        content: &str,
+        generation: &Generation,
+    ) -> Result<Option<SystemTime>> {
+        let conn = Arc::clone(&self.conn);
+        let site = self.site.clone();
         let path = path.to_string();
         let query = query.to_string();
         let content = content.to_string();
+        let generation = generation.clone();
 
         tokio::task::Builder::new()
             .name("db-set")
+            .spawn_blocking(move || {
+                let mut conn = conn.lock().unwrap();
+                let now = unix_time()?;
+                let tx = conn.transaction()?;
+                let version: u32 = tx.query_row(
+                    "SELECT COALESCE(MAX(version), 0) + 1 FROM resource_versions
+                    WHERE site = ?4 AND path = ?1 AND query = ?2 AND mime_type = ?3",
+                    params![path, query, generation.mime_type, site],
+                    |row| row.get(0),
+                )?;
+                tx.execute(
+                    "INSERT INTO resource_versions (path, query, mime_type, version, content, model,
+                        system_prompt_hash, method, status, duration_ms, created_at, site)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+                    params![
+                        path,
+                        query,
+                        generation.mime_type,
+                        version,
+                        content,
+                        generation.model,
+                        generation.system_prompt_hash,
+                        generation.method,
+                        generation.status,
+                        generation.duration.as_millis() as i64,
+                        now,
+                        site
+                    ],
+                )?;
+                let served = tx.execute(
+                    "INSERT INTO resources (path, query, content, mime_type, model, system_prompt_hash,
+                        method, status, duration_ms, created_at, updated_at, accessed_at, version, site)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?10, ?10, ?11, ?12)
+                    ON CONFLICT (site, path, query, mime_type) DO UPDATE SET
+                        content = excluded.content,
+                        model = excluded.model,
+                        system_prompt_hash = excluded.system_prompt_hash,
+                        method = excluded.method,
+                        status = excluded.status,
+                        duration_ms = excluded.duration_ms,
+                        updated_at = excluded.updated_at,
+                        version = excluded.version
+                    WHERE NOT pinned AND NOT authored",
+                    params![
+                        path,
+                        query,
+                        content,
+                        generation.mime_type,
+                        generation.model,
+                        generation.system_prompt_hash,
+                        generation.method,
+                        generation.status,
+                        generation.duration.as_millis() as i64,
+                        now,
+                        version,
+                        site
+                    ],
+                )?;
+                insert_usage(&tx, &site, &path, &query, &generation)?;
+                tx.commit()?;
+                Ok((served > 0).then(|| UNIX_EPOCH + Duration::from_secs(now as u64)))
+            })?
+            .await?
+    }
+
+    /// Store hand-written content as a new version, served from then on in place of any generated content.
+    /// Imported content is never regenerated, expired or evicted. Returns the new version.
+    pub async fn import(
+        &self,
+        path: &str,
+        query: &str,
+        mime_type: &str,
+        content: &str,
+    ) -> Result<u32> {
+        let conn = Arc::clone(&self.conn);
+        let site = self.site.clone();
+        let path = path.to_string();
+        let query = query.to_string();
+        let mime_type = mime_type.to_string();
+        let content = content.to_string();
+
+        tokio::task::Builder::new()
+            .name("db-import")
+            .spawn_blocking(move || {
+                let mut conn = conn.lock().unwrap();
+                let now = unix_time()?;
+                let tx = conn.transaction()?;
+                let version: u32 = tx.query_row(
+                    "SELECT COALESCE(MAX(version), 0) + 1 FROM resource_versions
+                    WHERE site = ?4 AND path = ?1 AND query = ?2 AND mime_type = ?3",
+                    params![path, query, mime_type, site],
+                    |row| row.get(0),
+                )?;
+                tx.execute(
+                    "INSERT INTO resource_versions (path, query, mime_type, version, content, status,
+                        created_at, authored, site)
+                    VALUES (?1, ?2, ?3, ?4, ?5, 200, ?6, 1, ?7)",
+                    params![path, query, mime_type, version, content, now, site],
+                )?;
+                tx.execute(
+                    "INSERT INTO resources (path, query, mime_type, content, status, created_at, updated_at,
+                        accessed_at, version, authored, site)
+                    VALUES (?1, ?2, ?3, ?4, 200, ?5, ?5, ?5, ?6, 1, ?7)
+                    ON CONFLICT (site, path, query, mime_type) DO UPDATE SET
+                        content = excluded.content,
+                        model = NULL,
+                        system_prompt_hash = NULL,
+                        method = NULL,
+                        status = excluded.status,
+                        duration_ms = NULL,
+                        updated_at = excluded.updated_at,
+                        version = excluded.version,
+                        pinned = 0,
+                        authored = 1",
+                    params![path, query, mime_type, content, now, version, site],
+                )?;
+                tx.commit()?;
+                Ok(version)
+            })?
+            .await?
+    }
+
+    /// Record the usage of a generation whose content isn't stored
+    pub async fn record_usage(
+        &self,
+        path: &str,
+        query: &str,
+        generation: &Generation,
+    ) -> Result<()> {
+        let conn = Arc::clone(&self.conn);
+        let site = self.site.clone();
+        let path = path.to_string();
+        let query = query.to_string();
+        let generation = generation.clone();
+
+        tokio::task::Builder::new()
+            .name("db-record-usage")
             .spawn_blocking(move || {
                 let conn = conn.lock().unwrap();
-                conn.execute(
-                    "INSERT OR REPLACE INTO resources (path, query, content) VALUES (?1, ?2, ?3)",
-                    params![path, query, content],
+                insert_usage(&conn, &site, &path, &query, &generation)
+            })?
+            .await?
+    }
+
+    /// Record facts established by the page at `path`, keeping the earlier statement of facts already recorded.
+    /// Returns the number of new facts.
+    pub async fn add_facts(&self, path: &str, facts: &[Fact]) -> Result<usize> {
+        let conn = Arc::clone(&self.conn);
+        let site = self.site.clone();
+        let path = path.to_string();
+        let facts = facts.to_vec();
+
+        tokio::task::Builder::new()
+            .name("db-add-facts")
+            .spawn_blocking(move || {
+                let mut conn = conn.lock().unwrap();
+                let tx = conn.transaction()?;
+                let now = unix_time()?;
+                let mut added = 0;
+                for fact in &facts {
+                    added += tx.execute(
+                        "INSERT INTO facts (site, name, statement, path, created_at)
+                        VALUES (?1, ?2, ?3, ?4, ?5)
+                        ON CONFLICT (site, name) DO NOTHING",
+                        params![site, fact.name, fact.statement, path, now],
+                    )?;
+                }
+                tx.commit()?;
+                Ok(added)
+            })?
+            .await?
+    }
+
+    /// The `limit` most recently recorded facts, oldest first
+    pub async fn facts(&self, limit: usize) -> Result<Vec<Fact>> {
+        let conn = Arc::clone(&self.conn);
+        let site = self.site.clone();
+
+        tokio::task::Builder::new()
+            .name("db-facts")
+            .spawn_blocking(move || {
+                let conn = conn.lock().unwrap();
+                let mut stmt = conn.prepare(
+                    "SELECT name, statement FROM (
+                        SELECT name, statement, created_at, rowid FROM facts
+                        WHERE site = ?1
+                        ORDER BY created_at DESC, rowid DESC
+                        LIMIT ?2
+                    )
+                    ORDER BY created_at, rowid",
                 )?;
-                Ok(())
+                let rows = stmt.query_map(params![site, limit as i64], |row| {
+                    Ok(Fact {
+                        name: row.get(0)?,
+                        statement: row.get(1)?,
+                    })
+                })?;
+                Ok(rows.collect::<Result<Vec<_>, _>>()?)
             })?
             .await?
     }
+
+    /// Sum the site's usage for each model, content type and path
+    pub async fn usage_summary(&self) -> Result<Vec<UsageSummary>> {
+        let conn = Arc::clone(&self.conn);
+        let site = self.site.clone();
+
+        tokio::task::Builder::new()
+            .name("db-usage-summary")
+            .spawn_blocking(move || {
+                let conn = conn.lock().unwrap();
+                let mut stmt = conn.prepare(
+                    "SELECT model, content_type, path, COUNT(*), SUM(prompt_tokens),
+                        SUM(completion_tokens), TOTAL(cost)
+                    FROM usage
+                    WHERE site = ?1
+                    GROUP BY model, content_type, path
+                    ORDER BY path",
+                )?;
+                let rows = stmt.query_map([site], |row| {
+                    Ok(UsageSummary {
+                        model: row.get(0)?,
+                        content_type: row.get(1)?,
+                        path: row.get(2)?,
+                        totals: UsageTotals {
+                            generations: row.get(3)?,
+                            prompt_tokens: row.get(4)?,
+                            completion_tokens: row.get(5)?,
+                            cost: row.get(6)?,
+                        },
+                    })
+                })?;
+                Ok(rows.collect::<Result<Vec<_>, _>>()?)
+            })?
+            .await?
+    }
+
+    /// Sum usage since the start of the current UTC day and month
+    pub async fn spending(&self) -> Result<Spending> {
+        self.spending_where(false).await
+    }
+
+    /// Sum usage of background prefetches since the start of the current UTC day and month
+    pub async fn speculative_spending(&self) -> Result<Spending> {
+        self.spending_where(true).await
+    }
+
+    async fn spending_where(&self, speculative_only: bool) -> Result<Spending> {
+        let conn = Arc::clone(&self.conn);
+
+        tokio::task::Builder::new()
+            .name("db-spending")
+            .spawn_blocking(move || {
+                let conn = conn.lock().unwrap();
+                let totals_since = |start: &str| {
+                    conn.query_row(
+                        "SELECT COUNT(*), COALESCE(SUM(prompt_tokens), 0),
+                            COALESCE(SUM(completion_tokens), 0), TOTAL(cost)
+                        FROM usage
+                        WHERE created_at >= unixepoch('now', ?1) AND (speculative OR NOT ?2)",
+                        params![start, speculative_only],
+                        |row| {
+                            Ok(UsageTotals {
+                                generations: row.get(0)?,
+                                prompt_tokens: row.get(1)?,
+                                completion_tokens: row.get(2)?,
+                                cost: row.get(3)?,
+                            })
+                        },
+                    )
+                };
+
+                Ok(Spending {
+                    today: totals_since("start of day")?,
+                    this_month: totals_since("start of month")?,
+                })
+            })?
+            .await?
+    }
+}
+
+fn insert_usage(
+    conn: &Connection,
+    site: &str,
+    path: &str,
+    query: &str,
+    generation: &Generation,
+) -> Result<()> {
+    conn.execute(
+        "INSERT INTO usage (path, query, model, content_type, prompt_tokens, completion_tokens, cost, created_at, speculative, site)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+        params![
+            path,
+            query,
+            generation.model,
+            generation.mime_type,
+            generation.usage.prompt_tokens as i64,
+            generation.usage.completion_tokens as i64,
+            generation.usage.cost,
+            unix_time()?,
+            generation.speculative,
+            site
+        ],
+    )?;
+    Ok(())
+}
+
+/// Current time in seconds since the Unix epoch, as stored in timestamp columns
+fn unix_time() -> Result<i64> {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generation(mime_type: &str) -> Generation {
+        Generation {
+            model: "model".to_string(),
+            mime_type: mime_type.to_string(),
+            system_prompt_hash: String::new(),
+            method: "GET".to_string(),
+            status: 200,
+            duration: Duration::ZERO,
+            usage: Usage::default(),
+            speculative: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_keys_resources_by_mime_type() {
+        let db = Database::new(None).unwrap();
+
+        db.set("/apples", "", "{}", &generation("application/json"))
+            .await
+            .unwrap();
+        assert_eq!(
+            db.get("/apples", "", "text/html", None).await.unwrap(),
+            None
+        );
+        // References fall back to other representations of the path
+        assert_eq!(
+            db.get_reference("/apples", "", "text/html").await.unwrap(),
+            Some("{}".to_string())
+        );
+
+        db.set("/apples", "", "<html>", &generation("text/html"))
+            .await
+            .unwrap();
+        assert_eq!(
+            db.get("/apples", "", "application/json", None)
+                .await
+                .unwrap()
+                .map(|cached| cached.content),
+            Some("{}".to_string())
+        );
+        assert_eq!(
+            db.get_reference("/apples", "", "text/html").await.unwrap(),
+            Some("<html>".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_versions_pin_and_rollback() {
+        let db = Database::new(None).unwrap();
+        let html = generation("text/html");
+        let current = || async {
+            db.get("/apples", "", "text/html", None)
+                .await
+                .unwrap()
+                .unwrap()
+                .content
+        };
+        let current_versions = || async {
+            db.versions("/apples", "", "text/html")
+                .await
+                .unwrap()
+                .iter()
+                .map(|v| (v.version, v.current, v.pinned))
+                .collect::<Vec<_>>()
+        };
+
+        for content in ["one", "two", "three"] {
+            db.set("/apples", "", content, &html).await.unwrap();
+        }
+        assert_eq!(current().await, "three");
+        assert_eq!(
+            db.get_version("/apples", "", "text/html", 2)
+                .await
+                .unwrap()
+                .unwrap(),
+            "two"
+        );
+
+        // A rolled back version is replaced by the next generation
+        assert!(db.rollback("/apples", "", "text/html", 1).await.unwrap());
+        assert_eq!(current().await, "one");
+        assert!(
+            db.set("/apples", "", "four", &html)
+                .await
+                .unwrap()
+                .is_some()
+        );
+        assert_eq!(current().await, "four");
+
+        // A pinned version is kept until unpinned
+        assert!(db.pin("/apples", "", "text/html", 2).await.unwrap());
+        assert_eq!(db.set("/apples", "", "five", &html).await.unwrap(), None);
+        assert_eq!(current().await, "two");
+        assert_eq!(
+            current_versions().await,
+            [
+                (1, false, false),
+                (2, true, true),
+                (3, false, false),
+                (4, false, false),
+                (5, false, false)
+            ]
+        );
+
+        assert!(db.unpin("/apples", "", "text/html").await.unwrap());
+        assert_eq!(current().await, "five");
+
+        assert!(!db.pin("/apples", "", "text/html", 9).await.unwrap());
+        assert!(!db.unpin("/pears", "", "text/html").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_purge_filters() {
+        let db = Database::new(None).unwrap();
+        let resources = [
+            ("/blog", "", "text/html", "a"),
+            ("/blog/post", "", "text/html", "a"),
+            ("/blog/post", "page=2", "text/html", "a"),
+            ("/blogs", "", "text/html", "a"),
+            ("/blog/post.json", "", "application/json", "b"),
+        ];
+        let reset = || async {
+            for (path, query, mime_type, model) in resources {
+                let mut generation = generation(mime_type);
+                generation.model = model.to_string();
+                db.set(path, query, "content", &generation).await.unwrap();
+            }
+        };
+        let db = &db;
+        let purge = |filter: PurgeFilter| async move { db.purge(&filter).await.unwrap() };
+
+        reset().await;
+        let path = |path: &str| Some(path.to_string());
+        assert_eq!(
+            purge(PurgeFilter {
+                path: path("/blog/post"),
+                ..Default::default()
+            })
+            .await,
+            2
+        );
+        assert_eq!(
+            purge(PurgeFilter {
+                prefix: path("/blog/"),
+                ..Default::default()
+            })
+            .await,
+            2
+        );
+        assert!(
+            db.get("/blogs", "", "text/html", None)
+                .await
+                .unwrap()
+                .is_some()
+        );
+
+        reset().await;
+        assert_eq!(
+            purge(PurgeFilter {
+                path: path("/blog/post"),
+                query: path("page=2"),
+                ..Default::default()
+            })
+            .await,
+            1
+        );
+        assert_eq!(
+            purge(PurgeFilter {
+                model: path("b"),
+                ..Default::default()
+            })
+            .await,
+            1
+        );
+        assert_eq!(
+            purge(PurgeFilter {
+                mime_type: path("text/html"),
+                ..Default::default()
+            })
+            .await,
+            3
+        );
+
+        // Versions are kept after purging
+        assert!(
+            db.get_version("/blog", "", "text/html", 1)
+                .await
+                .unwrap()
+                .is_some()
+        );
+
+        reset().await;
+        assert_eq!(purge(PurgeFilter::default()).await, 5);
+    }
+
+    #[tokio::test]
+    async fn test_evicts_expired_and_least_recently_used() {
+        let db = &Database::new(None).unwrap();
+        let html = generation("text/html");
+        let json = generation("application/json");
+        let age = |path: &str, secs: i64| {
+            db.conn
+                .lock()
+                .unwrap()
+                .execute(
+                    "UPDATE resources SET updated_at = updated_at - ?2, accessed_at = accessed_at - ?2
+                    WHERE path = ?1",
+                    params![path, secs],
+                )
+                .unwrap();
+        };
+        let stored = |path: &'static str, mime_type: &'static str| async move {
+            db.get(path, "", mime_type, None).await.unwrap().is_some()
+        };
+        let ttl = Some(Duration::from_secs(300));
+
+        db.set("/api/new", "", "{}", &json).await.unwrap();
+        db.set("/api/old", "", "{}", &json).await.unwrap();
+        db.set("/page", "", "<html>", &html).await.unwrap();
+        age("/api/old", 600);
+        age("/page", 600);
+
+        // Expired content is a miss even before it is evicted
+        assert!(
+            db.get("/api/new", "", "application/json", ttl)
+                .await
+                .unwrap()
+                .is_some()
+        );
+        assert!(
+            db.get("/api/old", "", "application/json", ttl)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        // TTLs can differ by path as well as by MIME type
+        let ttl = |path: &str, mime_type: &str| match mime_type {
+            "application/json" => Some(Duration::from_secs(300)),
+            _ if path == "/page" => Some(Duration::from_secs(3600)),
+            _ => None,
+        };
+        assert_eq!(db.evict_expired(ttl).await.unwrap(), 1);
+        assert!(!stored("/api/old", "application/json").await);
+        assert!(stored("/page", "text/html").await);
+
+        // Each resource is 10 bytes, and reading one marks it as recently used
+        db.conn
+            .lock()
+            .unwrap()
+            .execute("DELETE FROM resources", [])
+            .unwrap();
+        for (path, secs) in [("/a", 40), ("/b", 30), ("/c", 20), ("/d", 10)] {
+            db.set(path, "", "0123456789", &html).await.unwrap();
+            age(path, secs);
+        }
+        assert!(db.pin("/a", "", "text/html", 1).await.unwrap());
+        db.get("/b", "", "text/html", None).await.unwrap();
+
+        assert_eq!(db.evict_least_recently_used(30).await.unwrap(), 1);
+        assert!(!stored("/c", "text/html").await);
+        assert_eq!(db.evict_least_recently_used(30).await.unwrap(), 0);
+
+        // Pinned resources count towards the cap but are kept
+        assert_eq!(db.evict_least_recently_used(5).await.unwrap(), 2);
+        assert!(stored("/a", "text/html").await);
+    }
+
+    #[tokio::test]
+    async fn test_evicts_old_versions() {
+        let db = Database::new(None).unwrap();
+        let html = generation("text/html");
+        let versions = |path: &'static str| {
+            let db = &db;
+            async move {
+                db.versions(path, "", "text/html")
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|v| v.version)
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        // Each version is 10 bytes, and the newest are kept
+        for _ in 0..3 {
+            db.set("/a", "", "0123456789", &html).await.unwrap();
+        }
+        assert!(db.pin("/a", "", "text/html", 1).await.unwrap());
+        for _ in 0..2 {
+            db.set("/b", "", "0123456789", &html).await.unwrap();
+        }
+        db.import("/c", "", "text/html", "0123456789")
+            .await
+            .unwrap();
+        let purge_b = PurgeFilter {
+            path: Some("/b".to_string()),
+            ..PurgeFilter::default()
+        };
+        assert_eq!(db.purge(&purge_b).await.unwrap(), 1);
+
+        assert_eq!(db.evict_versions(40).await.unwrap(), 2);
+        assert_eq!(versions("/a").await, [1]);
+        assert_eq!(versions("/b").await, [1, 2]);
+        assert_eq!(versions("/c").await, [1]);
+
+        // Pinned and imported versions count towards the cap but are kept
+        assert_eq!(db.evict_versions(0).await.unwrap(), 2);
+        assert_eq!(versions("/a").await, [1]);
+        assert_eq!(versions("/b").await, Vec::<u32>::new());
+        assert_eq!(versions("/c").await, [1]);
+        assert!(db.get("/a", "", "text/html", None).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_imported_content_is_never_replaced() {
+        let db = Database::new(None).unwrap();
+        let css = generation("text/css");
+        let current = || async {
+            db.get("/style.css", "", "text/css", Some(Duration::ZERO))
+                .await
+                .unwrap()
+                .unwrap()
+        };
+
+        db.set("/style.css", "", "generated", &css).await.unwrap();
+        assert_eq!(
+            db.import("/style.css", "", "text/css", "authored")
+                .await
+                .unwrap(),
+            2
+        );
+        db.set("/style.css", "", "regenerated", &css).await.unwrap();
+
+        // Imported content doesn't expire, and is kept by purges and evictions
+        let cached = current().await;
+        assert_eq!(cached.content, "authored");
+        assert!(cached.authored);
+        assert_eq!(db.purge(&PurgeFilter::default()).await.unwrap(), 0);
+        assert_eq!(
+            db.evict_expired(|_, _| Some(Duration::ZERO)).await.unwrap(),
+            0
+        );
+        assert_eq!(db.evict_least_recently_used(0).await.unwrap(), 0);
+        assert_eq!(current().await.content, "authored");
+
+        // Rolling back to a generated version makes it replaceable again
+        assert!(db.rollback("/style.css", "", "text/css", 3).await.unwrap());
+        assert!(
+            db.get("/style.css", "", "text/css", Some(Duration::ZERO))
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert_eq!(db.purge(&PurgeFilter::default()).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_sites_are_isolated() {
+        let db = Database::new(None).unwrap();
+        let news = db.for_site("news");
+
+        db.set("/", "", "<h1>Intranet</h1>", &generation("text/html"))
+            .await
+            .unwrap();
+        news.set("/", "", "<h1>News</h1>", &generation("text/html"))
+            .await
+            .unwrap();
+        news.set("/", "", "<h1>Breaking news</h1>", &generation("text/html"))
+            .await
+            .unwrap();
+
+        async fn content(db: &Database) -> String {
+            db.get("/", "", "text/html", None)
+                .await
+                .unwrap()
+                .unwrap()
+                .content
+        }
+        assert_eq!(content(&db).await, "<h1>Intranet</h1>");
+        assert_eq!(content(&news).await, "<h1>Breaking news</h1>");
+        assert_eq!(db.versions("/", "", "text/html").await.unwrap().len(), 1);
+        assert_eq!(news.versions("/", "", "text/html").await.unwrap().len(), 2);
+
+        assert_eq!(news.purge(&PurgeFilter::default()).await.unwrap(), 1);
+        assert_eq!(db.resources().await.unwrap().len(), 1);
+        assert!(news.resources().await.unwrap().is_empty());
+
+        // Usage is reported per site but spending covers every site
+        let generations = |summaries: Vec<UsageSummary>| -> Vec<(String, u64)> {
+            summaries
+                .into_iter()
+                .map(|summary| (summary.path, summary.totals.generations))
+                .collect()
+        };
+        assert_eq!(
+            generations(db.usage_summary().await.unwrap()),
+            [("/".to_string(), 1)]
+        );
+        assert_eq!(
+            generations(news.usage_summary().await.unwrap()),
+            [("/".to_string(), 2)]
+        );
+        assert_eq!(db.spending().await.unwrap().today.generations, 3);
+    }
+
+    #[tokio::test]
+    async fn test_facts() {
+        let db = Database::new(None).unwrap();
+        let news = db.for_site("news");
+        let fact = |name: &str, statement: &str| Fact {
+            name: name.to_string(),
+            statement: statement.to_string(),
+        };
+
+        let facts = [fact("CEO", "Jane Doe"), fact("Founded", "1987")];
+        assert_eq!(db.add_facts("/about", &facts).await.unwrap(), 2);
+        let facts = [fact("CEO", "John Doe"), fact("Offices", "Berlin")];
+        assert_eq!(db.add_facts("/team", &facts).await.unwrap(), 1);
+        news.add_facts("/", &[fact("Editor", "Perry White")])
+            .await
+            .unwrap();
+
+        // Facts stated first are kept, and only the most recent are listed
+        assert_eq!(
+            db.facts(10).await.unwrap(),
+            [
+                fact("CEO", "Jane Doe"),
+                fact("Founded", "1987"),
+                fact("Offices", "Berlin")
+            ]
+        );
+        assert_eq!(
+            db.facts(2).await.unwrap(),
+            [fact("Founded", "1987"), fact("Offices", "Berlin")]
+        );
+        assert_eq!(
+            news.facts(10).await.unwrap(),
+            [fact("Editor", "Perry White")]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_speculative_spending() {
+        let db = Database::new(None).unwrap();
+        let with_tokens = |speculative| Generation {
+            usage: Usage {
+                prompt_tokens: 10,
+                completion_tokens: 5,
+                cost: Some(0.25),
+            },
+            speculative,
+            ..generation("text/html")
+        };
+
+        db.set("/apples", "", "<p>apples</p>", &with_tokens(false))
+            .await
+            .unwrap();
+        db.set("/pears", "", "<p>pears</p>", &with_tokens(true))
+            .await
+            .unwrap();
+
+        let spending = db.spending().await.unwrap();
+        assert_eq!(spending.today.generations, 2);
+        assert_eq!(spending.this_month.cost, 0.5);
+        let speculative = db.speculative_spending().await.unwrap();
+        assert_eq!(speculative.today.generations, 1);
+        assert_eq!(speculative.today.prompt_tokens, 10);
+        assert_eq!(speculative.this_month.cost, 0.25);
+    }
+
+    #[tokio::test]
+    async fn test_migrates_unversioned_database() {
+        let path =
+            std::env::temp_dir().join(format!("websim-{}-migrate.sqlite", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        // Schema created by releases before migrations were tracked
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE resources (
+                path TEXT NOT NULL,
+                query TEXT NOT NULL,
+                content TEXT NOT NULL,
+                PRIMARY KEY (path, query)
+            );
+            INSERT INTO resources (path, query, content) VALUES ('/old', '', 'old content');",
+        )
+        .unwrap();
+        drop(conn);
+
+        let db = Database::new(Some(path.clone())).unwrap();
+        assert_eq!(
+            db.get("/old", "", "text/html", None).await.unwrap(),
+            Some(CachedContent {
+                content: "old content".to_string(),
+                last_modified: None,
+                authored: false,
+            })
+        );
+
+        let generation = Generation {
+            model: "model".to_string(),
+            mime_type: "text/html".to_string(),
+            system_prompt_hash: "hash".to_string(),
+            method: "GET".to_string(),
+            status: 200,
+            duration: Duration::from_millis(1500),
+            usage: Usage::default(),
+            speculative: false,
+        };
+        db.set("/old", "", "new content", &generation)
+            .await
+            .unwrap();
+        drop(db);
+
+        // Reopening an up to date database applies no further migrations
+        let db = Database::new(Some(path)).unwrap();
+        let conn = db.conn.lock().unwrap();
+        let version: usize = conn
+            .pragma_query_value(None, "user_version", |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, MIGRATIONS.len());
+
+        let (content, mime_type, duration_ms, created_at, updated_at): (
+            String,
+            String,
+            i64,
+            Option<i64>,
+            i64,
+        ) = conn
+            .query_row(
+                "SELECT content, mime_type, duration_ms, created_at, updated_at FROM resources",
+                [],
+                |row| {
+                    Ok((
+                        row.get(0)?,
+                        row.get(1)?,
+                        row.get(2)?,
+                        row.get(3)?,
+                        row.get(4)?,
+                    ))
+                },
+            )
+            .unwrap();
+        assert_eq!(content, "new content");
+        assert_eq!(mime_type, "text/html");
+        assert_eq!(duration_ms, 1500);
+        // The row predates timestamps, so only the update is known
+        assert_eq!(created_at, None);
+        assert!(updated_at > 0);
+    }
 }
@@ -0,0 +1,62 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use tracing::{info, warn};
+
+use crate::config::ContentTypeConfig;
+use crate::state::AppState;
+
+/// Removes cached resources that have outlived their TTL, as set by their content type or the route
+/// matching their path, then the least recently
+/// served ones while the cache is over its size cap, and then the oldest versions no longer served
+/// while the versions kept are over it.
+pub async fn evict(state: &AppState) -> Result<()> {
+    let evicted = state
+        .db
+        .evict_expired(|path, mime_type| {
+            state
+                .routes
+                .content_type(&state.config, path, mime_type)
+                .and_then(ContentTypeConfig::ttl)
+        })
+        .await?;
+    if evicted > 0 {
+        info!(evicted = evicted, "Evicted expired resources");
+    }
+
+    if let Some(max_bytes) = state.config.cache.max_bytes {
+        let evicted = state.db.evict_least_recently_used(max_bytes).await?;
+        if evicted > 0 {
+            info!(
+                max_bytes = max_bytes,
+                evicted = evicted,
+                "Evicted least recently served resources"
+            );
+        }
+
+        let evicted = state.db.evict_versions(max_bytes).await?;
+        if evicted > 0 {
+            info!(
+                max_bytes = max_bytes,
+                evicted = evicted,
+                "Evicted old versions"
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Evicts resources at the configured interval for as long as the server runs
+pub async fn run(state: Arc<AppState>) {
+    let period = Duration::from_secs(state.config.cache.eviction_interval_secs.max(1));
+    let mut interval = tokio::time::interval(period);
+
+    loop {
+        interval.tick().await;
+        if let Err(e) = evict(&state).await {
+            warn!(error = %e, "Cache eviction failed");
+        }
+    }
+}
@@ -0,0 +1,358 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::sync::LazyLock;
+
+use anyhow::{Context, Result};
+use percent_encoding::{AsciiSet, CONTROLS, utf8_percent_encode};
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use url::Url;
+
+use crate::db::{Database, Resource};
+use crate::utils::normalize_path;
+
+/// Name of the file listing every exported resource, written to the root of the export
+pub const MANIFEST_FILE: &str = "websim-manifest.json";
+
+/// Characters escaped in exported file names, which are invalid in file names or would change the meaning of a path
+const FILE_NAME: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'*')
+    .add(b'/')
+    .add(b':')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'\\')
+    .add(b'|');
+
+/// Characters escaped in links to exported files
+const LINK: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'\'')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?');
+
+/// HTML attributes holding a URL, with the value in group 3 if double quoted or 4 if single quoted
+static URL_ATTRIBUTE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?i)\b(href|src|action)(\s*=\s*)(?:"([^"]*)"|'([^']*)')"#).unwrap()
+});
+
+/// Index of an exported site, mapping each resource to the file it was written to
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    pub resources: Vec<ManifestEntry>,
+}
+
+/// An exported resource
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub path: String,
+    pub query: String,
+    pub mime_type: String,
+    /// File relative to the export directory, with `/` separators
+    pub file: String,
+    pub version: u32,
+    pub model: Option<String>,
+    /// Seconds since the Unix epoch
+    pub updated_at: Option<i64>,
+}
+
+/// Writes every resource being served to `out` as a static site and returns its manifest.
+/// Links between exported HTML pages are rewritten to relative file paths, so the site can be
+/// browsed from any web server or straight from disk.
+/// Resources mapping to a file an earlier one was exported to, e.g. `/index.html` after `/`, are skipped.
+pub async fn export_site(db: &Database, out: &Path) -> Result<Manifest> {
+    let mut exported: Vec<(Resource, String)> = Vec::new();
+    let mut claimed = ClaimedFiles::default();
+    for resource in db.resources().await? {
+        match file_for(&resource.path, &resource.query, &resource.mime_type) {
+            Some(file) if claimed.claim(&file) => exported.push((resource, file)),
+            Some(file) => warn!(
+                path = %resource.path,
+                query = %resource.query,
+                mime_type = %resource.mime_type,
+                file,
+                "Skipping resource with a file another resource was exported to"
+            ),
+            None => {
+                warn!(path = %resource.path, "Skipping resource with a path that can't be exported")
+            }
+        }
+    }
+
+    // Links can't select a representation, so they go to the HTML one where there are several
+    let mut files: HashMap<(&str, &str), &str> = HashMap::new();
+    for (resource, file) in &exported {
+        let key = (resource.path.as_str(), resource.query.as_str());
+        if resource.mime_type == "text/html" || !files.contains_key(&key) {
+            files.insert(key, file);
+        }
+    }
+
+    for (resource, file) in &exported {
+        let target = out.join(file);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+        }
+        let content = if resource.mime_type == "text/html" {
+            rewrite_links(&resource.content, &resource.path, file, &files)
+        } else {
+            resource.content.clone()
+        };
+        fs::write(&target, content)
+            .with_context(|| format!("Failed to write {}", target.display()))?;
+    }
+
+    let manifest = Manifest {
+        resources: exported
+            .into_iter()
+            .map(|(resource, file)| ManifestEntry {
+                path: resource.path,
+                query: resource.query,
+                mime_type: resource.mime_type,
+                file,
+                version: resource.version,
+                model: resource.model,
+                updated_at: resource.updated_at,
+            })
+            .collect(),
+    };
+    let manifest_path = out.join(MANIFEST_FILE);
+    fs::write(&manifest_path, serde_json::to_string_pretty(&manifest)?)
+        .with_context(|| format!("Failed to write {}", manifest_path.display()))?;
+
+    Ok(manifest)
+}
+
+/// Maps a resource to a file relative to the export directory, e.g. `/articles/2023` to
+/// `articles/2023/index.html` and `/logo.svg?color=red` to `logo@color=red.svg`.
+/// Returns `None` for paths with `.` or `..` segments, which would escape their directory.
+pub fn file_for(path: &str, query: &str, mime_type: &str) -> Option<String> {
+    let extension = extension_for(mime_type);
+    let mut segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    if segments.iter().any(|s| *s == "." || *s == "..") {
+        return None;
+    }
+
+    // Paths without a matching extension become index files, leaving room for the paths below them
+    let mut name = "index";
+    if let Some((stem, ext)) = segments.last().copied().and_then(|s| s.rsplit_once('.'))
+        && !stem.is_empty()
+        && ext.eq_ignore_ascii_case(extension)
+    {
+        name = stem;
+        segments.pop();
+    }
+
+    let file_name = if query.is_empty() {
+        format!("{}.{}", name, extension)
+    } else {
+        format!("{}@{}.{}", name, query, extension)
+    };
+
+    Some(
+        segments
+            .into_iter()
+            .chain(std::iter::once(file_name.as_str()))
+            .map(|segment| utf8_percent_encode(segment, FILE_NAME).to_string())
+            .collect::<Vec<_>>()
+            .join("/"),
+    )
+}
+
+/// Files claimed by exported resources, and the directories they are in
+#[derive(Debug, Default)]
+struct ClaimedFiles {
+    files: HashSet<String>,
+    dirs: HashSet<String>,
+}
+
+impl ClaimedFiles {
+    /// Claims `file` unless it was already claimed, as a file or a directory, or one of its directories was
+    /// claimed as a file
+    fn claim(&mut self, file: &str) -> bool {
+        let dirs: Vec<&str> = file.match_indices('/').map(|(i, _)| &file[..i]).collect();
+        if self.files.contains(file)
+            || self.dirs.contains(file)
+            || dirs.iter().any(|dir| self.files.contains(*dir))
+        {
+            return false;
+        }
+        self.files.insert(file.to_string());
+        self.dirs.extend(dirs.into_iter().map(str::to_string));
+        true
+    }
+}
+
+/// File extension for content of a MIME type, e.g. `svg` for `image/svg+xml`
+fn extension_for(mime_type: &str) -> &str {
+    match mime_type {
+        "text/plain" => "txt",
+        "application/javascript" | "text/javascript" => "js",
+        _ => mime_type
+            .split('/')
+            .nth(1)
+            .and_then(|subtype| subtype.split(['+', ';']).next())
+            .filter(|subtype| !subtype.is_empty())
+            .unwrap_or("bin"),
+    }
+}
+
+/// Rewrites links in an HTML page at `path`, exported to `file`, that point to other exported resources
+fn rewrite_links(
+    html: &str,
+    path: &str,
+    file: &str,
+    files: &HashMap<(&str, &str), &str>,
+) -> String {
+    let Ok(base) = Url::parse(&format!("http://localhost{}", path)) else {
+        return html.to_string();
+    };
+
+    URL_ATTRIBUTE
+        .replace_all(html, |caps: &Captures| {
+            let (value, quote) = match (caps.get(3), caps.get(4)) {
+                (Some(value), _) => (value.as_str(), '"'),
+                (_, Some(value)) => (value.as_str(), '\''),
+                _ => return caps[0].to_string(),
+            };
+
+            let target = match base.join(value) {
+                Ok(target)
+                    if !value.is_empty()
+                        && !value.starts_with('#')
+                        && target.scheme() == base.scheme()
+                        && target.host_str() == base.host_str() =>
+                {
+                    target
+                }
+                _ => return caps[0].to_string(),
+            };
+            let key = (normalize_path(target.path()), target.query().unwrap_or(""));
+            let Some(target_file) = files.get(&key) else {
+                return caps[0].to_string();
+            };
+
+            let mut link = utf8_percent_encode(&relative_link(file, target_file), LINK).to_string();
+            if let Some(fragment) = target.fragment() {
+                link.push('#');
+                link.push_str(fragment);
+            }
+            format!("{}{}{}{}{}", &caps[1], &caps[2], quote, link, quote)
+        })
+        .into_owned()
+}
+
+/// Path to `to` relative to the directory of `from`, both relative to the export directory
+fn relative_link(from: &str, to: &str) -> String {
+    let mut from_dir: Vec<&str> = from.split('/').collect();
+    from_dir.pop();
+    let to: Vec<&str> = to.split('/').collect();
+    let common = from_dir
+        .iter()
+        .zip(&to)
+        .take_while(|(a, b)| a == b)
+        .count()
+        .min(to.len() - 1);
+
+    std::iter::repeat_n("..", from_dir.len() - common)
+        .chain(to[common..].iter().copied())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_for() {
+        let file = |path, query, mime_type| file_for(path, query, mime_type).unwrap();
+
+        assert_eq!(file("/", "", "text/html"), "index.html");
+        assert_eq!(
+            file("/articles/2023", "", "text/html"),
+            "articles/2023/index.html"
+        );
+        assert_eq!(file("/about.html", "", "text/html"), "about.html");
+        assert_eq!(file("/logo.svg", "", "image/svg+xml"), "logo.svg");
+        assert_eq!(file("/apples", "", "application/json"), "apples/index.json");
+        assert_eq!(
+            file("/apples", "color=green&size=big%20one", "text/html"),
+            "apples/index@color=green&size=big%2520one.html"
+        );
+        assert_eq!(
+            file("/logo.svg", "color=red", "image/svg+xml"),
+            "logo@color=red.svg"
+        );
+        assert_eq!(file("/a:b", "", "text/html"), "a%3Ab/index.html");
+        assert_eq!(file_for("/a/../b", "", "text/html"), None);
+    }
+
+    #[test]
+    fn test_claimed_files() {
+        let mut claimed = ClaimedFiles::default();
+        assert!(claimed.claim(&file_for("/", "", "text/html").unwrap()));
+        assert!(!claimed.claim(&file_for("/index.html", "", "text/html").unwrap()));
+        assert!(claimed.claim(&file_for("/x", "", "text/html").unwrap()));
+        assert!(!claimed.claim(&file_for("/x/index.html", "", "text/html").unwrap()));
+        assert!(claimed.claim(&file_for("/x/y", "", "text/html").unwrap()));
+
+        // A file can't also be a directory, whichever is exported first
+        assert!(claimed.claim(&file_for("/a.json", "", "application/json").unwrap()));
+        assert!(!claimed.claim(&file_for("/a.json", "", "text/html").unwrap()));
+        assert!(claimed.claim(&file_for("/b.json", "", "text/html").unwrap()));
+        assert!(!claimed.claim(&file_for("/b.json", "", "application/json").unwrap()));
+    }
+
+    #[test]
+    fn test_relative_link() {
+        assert_eq!(
+            relative_link("fruits/apples/index.html", "fruits/apples.svg"),
+            "../apples.svg"
+        );
+        assert_eq!(
+            relative_link("fruits/apples/index.html", "index.html"),
+            "../../index.html"
+        );
+        assert_eq!(
+            relative_link("index.html", "fruits/index.html"),
+            "fruits/index.html"
+        );
+        assert_eq!(relative_link("index.html", "index.html"), "index.html");
+    }
+
+    #[test]
+    fn test_rewrite_links() {
+        let files = HashMap::from([
+            (("/", ""), "index.html"),
+            (("/fruits", ""), "fruits/index.html"),
+            (("/fruits/apples.svg", ""), "fruits/apples.svg"),
+            (
+                ("/fruits/apples", "color=green"),
+                "fruits/apples/index@color=green.html",
+            ),
+        ]);
+        let html = r##"<a href="/">Home</a><a href='../'>Up</a><img src="apples.svg">
+            <a href="?color=green#top">Green</a><a href="/pears">Pears</a>
+            <a href="https://example.com/">Elsewhere</a><a href="#top">Top</a>"##;
+
+        assert_eq!(
+            rewrite_links(html, "/fruits/apples", "fruits/apples/index.html", &files),
+            r##"<a href="../../index.html">Home</a><a href='../../index.html'>Up</a><img src="../apples.svg">
+            <a href="index@color=green.html#top">Green</a><a href="/pears">Pears</a>
+            <a href="https://example.com/">Elsewhere</a><a href="#top">Top</a>"##
+        );
+    }
+}
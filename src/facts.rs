@@ -0,0 +1,137 @@
+use serde::Serialize;
+use tracing::warn;
+
+/// Opening tag of the block the model records facts in, after the content it generates
+pub const OPEN_TAG: &str = "<websim-facts>";
+const CLOSE_TAG: &str = "</websim-facts>";
+
+/// Asks the model to record the facts a page establishes, given in the user prompt of sites that keep them
+pub const INSTRUCTIONS: &str = r#"After the content, record any new names, dates, figures or other details it establishes that later pages must agree with, as a JSON object of short fact names to statements inside <websim-facts></websim-facts> tags at the very end, e.g. <websim-facts>{"CEO": "Jane Doe has been CEO since 1987"}</websim-facts>. Leave out facts already listed."#;
+
+/// A detail established by a generated page, e.g. the name of a character or the price of a product
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Fact {
+    pub name: String,
+    pub statement: String,
+}
+
+/// Splits generated content from the facts block the model appended to it.
+/// Blocks that aren't a JSON object are dropped with a warning, so they are never served.
+pub fn split(content: &str) -> (&str, Vec<Fact>) {
+    let Some(start) = content.find(OPEN_TAG) else {
+        return (content, Vec::new());
+    };
+    let block = &content[start + OPEN_TAG.len()..];
+    let block = block.find(CLOSE_TAG).map_or(block, |end| &block[..end]);
+
+    let facts = match serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(block) {
+        Ok(object) => object
+            .into_iter()
+            .filter(|(name, _)| !name.trim().is_empty())
+            .map(|(name, value)| Fact {
+                name: name.trim().to_string(),
+                statement: match value {
+                    serde_json::Value::String(statement) => statement,
+                    value => value.to_string(),
+                },
+            })
+            .collect(),
+        Err(e) => {
+            warn!(error = %e, "Ignoring malformed facts block");
+            Vec::new()
+        }
+    };
+
+    (content[..start].trim_end(), facts)
+}
+
+/// Holds back streamed content from the start of the facts block on, so clients never see it and are sent
+/// the same content as is stored
+#[derive(Debug, Default)]
+pub struct StreamFilter {
+    /// Content that may be the whitespace before the facts block or the start of its opening tag
+    pending: String,
+    in_block: bool,
+}
+
+impl StreamFilter {
+    /// Takes the next chunk of streamed content, returning the part that can be sent on
+    pub fn push(&mut self, delta: &str) -> String {
+        if self.in_block {
+            return String::new();
+        }
+        self.pending.push_str(delta);
+
+        if let Some(start) = self.pending.find(OPEN_TAG) {
+            self.in_block = true;
+            let mut forward = std::mem::take(&mut self.pending);
+            forward.truncate(forward[..start].trim_end().len());
+            return forward;
+        }
+
+        // Keep back the longest ending that the opening tag could continue from, and the whitespace before it
+        let tag_start = (1..OPEN_TAG.len())
+            .rev()
+            .find(|&len| self.pending.ends_with(&OPEN_TAG[..len]))
+            .map_or(self.pending.len(), |len| self.pending.len() - len);
+        let held_from = self.pending[..tag_start].trim_end().len();
+        self.pending.drain(..held_from).collect()
+    }
+
+    /// Content held back when the stream ended without a facts block
+    pub fn finish(self) -> String {
+        self.pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fact(name: &str, statement: &str) -> Fact {
+        Fact {
+            name: name.to_string(),
+            statement: statement.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_split() {
+        assert_eq!(split("<h1>Hi</h1>"), ("<h1>Hi</h1>", Vec::new()));
+        assert_eq!(
+            split(
+                "<h1>Hi</h1>\n<websim-facts>{\"CEO\": \"Jane Doe\", \" founded \": 1987, \"\": \"x\"}</websim-facts>\n"
+            ),
+            (
+                "<h1>Hi</h1>",
+                vec![fact("CEO", "Jane Doe"), fact("founded", "1987")]
+            )
+        );
+        // Unterminated and malformed blocks are still cut off
+        assert_eq!(
+            split("{}<websim-facts>{\"CEO\": \"Jane Doe\"}"),
+            ("{}", vec![fact("CEO", "Jane Doe")])
+        );
+        assert_eq!(
+            split("{}<websim-facts>CEO: Jane Doe</websim-facts>"),
+            ("{}", Vec::new())
+        );
+    }
+
+    #[test]
+    fn test_stream_filter() {
+        let mut filter = StreamFilter::default();
+        assert_eq!(filter.push("<h1>Hi</h1><"), "<h1>Hi</h1>");
+        assert_eq!(filter.push("p>\n"), "<p>");
+        assert_eq!(filter.push("\n<websim-"), "");
+        assert_eq!(filter.push("facts>{\"CEO\""), "");
+        assert_eq!(filter.push(": \"Jane Doe\"}</websim-facts>"), "");
+        assert_eq!(filter.finish(), "");
+
+        let mut filter = StreamFilter::default();
+        assert_eq!(filter.push("a <web"), "a");
+        assert_eq!(filter.push("b"), " <webb");
+        assert_eq!(filter.push(" \n"), "");
+        assert_eq!(filter.finish(), " \n");
+    }
+}
@@ -0,0 +1,276 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result, anyhow};
+use async_trait::async_trait;
+use futures_util::{StreamExt, stream};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+
+use crate::backend::{
+    ChatCompletionRequest, ChatCompletionResponse, Choice, ContentStream, LlmBackend, Message,
+    MessageRole, StreamDelta, Usage,
+};
+
+/// A recorded request and the response it produced
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Fixture {
+    pub request: FixtureRequest,
+    pub response: ChatCompletionResponse,
+}
+
+/// The parts of a request that identify a fixture
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FixtureRequest {
+    pub model: String,
+    pub messages: Vec<Message>,
+}
+
+/// Computes the fixture key for a request from a hash of its model and messages
+pub fn fixture_key(request: &ChatCompletionRequest) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(request.model.as_bytes());
+    for message in &request.messages {
+        hasher.update([0]);
+        hasher.update(message.role.to_string().as_bytes());
+        hasher.update([0]);
+        hasher.update(message.content.as_bytes());
+    }
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Fixture file holding recorded responses keyed by [`fixture_key`].
+/// Entries are kept sorted so recordings produce stable diffs.
+pub struct Fixtures {
+    path: PathBuf,
+    entries: Mutex<BTreeMap<String, Fixture>>,
+}
+
+impl Fixtures {
+    /// Loads fixtures from `path`, starting empty if the file doesn't exist yet
+    pub fn load(path: PathBuf) -> Result<Self> {
+        let entries = if path.exists() {
+            let contents = std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read fixtures from: {}", path.display()))?;
+            serde_json::from_str(&contents)
+                .with_context(|| format!("Failed to parse fixtures from: {}", path.display()))?
+        } else {
+            BTreeMap::new()
+        };
+
+        info!("Loaded {} fixtures from {}", entries.len(), path.display());
+
+        Ok(Self {
+            path,
+            entries: Mutex::new(entries),
+        })
+    }
+
+    fn get(&self, key: &str) -> Option<Fixture> {
+        self.entries.lock().unwrap().get(key).cloned()
+    }
+
+    /// Adds a fixture and rewrites the fixture file
+    async fn insert(self: Arc<Self>, key: String, fixture: Fixture) -> Result<()> {
+        tokio::task::Builder::new()
+            .name("fixtures-write")
+            .spawn_blocking(move || {
+                let mut entries = self.entries.lock().unwrap();
+                entries.insert(key, fixture);
+                let contents = serde_json::to_string_pretty(&*entries)?;
+                std::fs::write(&self.path, contents).with_context(|| {
+                    format!("Failed to write fixtures to: {}", self.path.display())
+                })
+            })?
+            .await?
+    }
+}
+
+/// Wraps a backend, recording every successful response to a fixture file
+pub struct RecordingBackend {
+    inner: Arc<dyn LlmBackend>,
+    fixtures: Arc<Fixtures>,
+}
+
+impl RecordingBackend {
+    pub fn new(inner: Arc<dyn LlmBackend>, fixtures: Arc<Fixtures>) -> Self {
+        Self { inner, fixtures }
+    }
+}
+
+async fn record(fixtures: Arc<Fixtures>, key: String, fixture: Fixture) {
+    match fixtures.insert(key.clone(), fixture).await {
+        Ok(_) => info!(key = %key, "Recorded fixture"),
+        Err(e) => warn!(key = %key, error = %e, "Failed to record fixture"),
+    }
+}
+
+struct RecordState {
+    inner: ContentStream,
+    fixtures: Arc<Fixtures>,
+    key: String,
+    request: FixtureRequest,
+    content: String,
+    usage: Option<Usage>,
+    done: bool,
+}
+
+#[async_trait]
+impl LlmBackend for RecordingBackend {
+    async fn chat_completion(
+        &self,
+        request: ChatCompletionRequest,
+    ) -> Result<ChatCompletionResponse> {
+        let key = fixture_key(&request);
+        let fixture_request = FixtureRequest {
+            model: request.model.clone(),
+            messages: request.messages.clone(),
+        };
+
+        let response = self.inner.chat_completion(request).await?;
+
+        let fixture = Fixture {
+            request: fixture_request,
+            response: response.clone(),
+        };
+        record(Arc::clone(&self.fixtures), key, fixture).await;
+
+        Ok(response)
+    }
+
+    async fn chat_completion_stream(
+        &self,
+        request: ChatCompletionRequest,
+    ) -> Result<ContentStream> {
+        let state = RecordState {
+            key: fixture_key(&request),
+            request: FixtureRequest {
+                model: request.model.clone(),
+                messages: request.messages.clone(),
+            },
+            inner: self.inner.chat_completion_stream(request).await?,
+            fixtures: Arc::clone(&self.fixtures),
+            content: String::new(),
+            usage: None,
+            done: false,
+        };
+
+        // Pass deltas through, recording the accumulated content only if the stream completes
+        let stream = stream::unfold(state, |mut state| async move {
+            if state.done {
+                return None;
+            }
+
+            match state.inner.next().await {
+                Some(Ok(delta)) => {
+                    match &delta {
+                        StreamDelta::Content(content) => state.content.push_str(content),
+                        StreamDelta::Usage(usage) => {
+                            state.usage.get_or_insert_default().merge(*usage)
+                        }
+                    }
+                    Some((Ok(delta), state))
+                }
+                Some(Err(e)) => {
+                    state.done = true;
+                    Some((Err(e), state))
+                }
+                None => {
+                    let fixture = Fixture {
+                        response: assistant_response(
+                            &state.request.model,
+                            state.content,
+                            state.usage,
+                        ),
+                        request: state.request,
+                    };
+                    record(state.fixtures, state.key, fixture).await;
+                    None
+                }
+            }
+        });
+
+        Ok(Box::pin(stream))
+    }
+}
+
+/// Serves responses only from a fixture file, failing on requests that were never recorded
+pub struct ReplayBackend {
+    fixtures: Arc<Fixtures>,
+}
+
+impl ReplayBackend {
+    pub fn new(fixtures: Arc<Fixtures>) -> Self {
+        Self { fixtures }
+    }
+
+    fn lookup(&self, request: &ChatCompletionRequest) -> Result<ChatCompletionResponse> {
+        let key = fixture_key(request);
+        match self.fixtures.get(&key) {
+            Some(fixture) => {
+                info!(key = %key, "Replaying fixture");
+                Ok(fixture.response)
+            }
+            None => Err(anyhow!(
+                "No fixture recorded for {} request to {} (key {})",
+                request.model,
+                request.path,
+                key
+            )),
+        }
+    }
+}
+
+#[async_trait]
+impl LlmBackend for ReplayBackend {
+    async fn chat_completion(
+        &self,
+        request: ChatCompletionRequest,
+    ) -> Result<ChatCompletionResponse> {
+        self.lookup(&request)
+    }
+
+    async fn chat_completion_stream(
+        &self,
+        request: ChatCompletionRequest,
+    ) -> Result<ContentStream> {
+        let response = self.lookup(&request)?;
+        let content = response
+            .choices
+            .into_iter()
+            .next()
+            .map(|choice| choice.message.content)
+            .unwrap_or_default();
+
+        let deltas = std::iter::once(StreamDelta::Content(content))
+            .chain(response.usage.map(StreamDelta::Usage))
+            .map(Ok);
+        Ok(Box::pin(stream::iter(deltas)))
+    }
+}
+
+/// Builds the response a completed stream is recorded as
+fn assistant_response(
+    model: &str,
+    content: String,
+    usage: Option<Usage>,
+) -> ChatCompletionResponse {
+    ChatCompletionResponse {
+        id: String::new(),
+        model: model.to_string(),
+        choices: vec![Choice {
+            message: Message {
+                role: MessageRole::Assistant,
+                content,
+            },
+            finish_reason: Some("stop".to_string()),
+        }],
+        usage,
+    }
+}
@@ -1,17 +1,29 @@
 use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 
+use axum::body::Body;
 use axum::extract::{Request, State};
-use axum::http::{HeaderMap, Method, StatusCode, Uri};
+use axum::http::{HeaderMap, HeaderValue, Method, StatusCode, Uri, header};
 use axum::response::{IntoResponse, Response};
+use bytes::Bytes;
+use futures_util::{StreamExt, stream};
 use minijinja::Environment;
-use tracing::{info, warn};
+use tokio::sync::mpsc;
+use tracing::{Instrument, info, warn};
 
-use crate::content_type;
-use crate::openrouter::{
-    ChatCompletionRequest, Message, MessageRole, OpenRouterClient, ProviderPrefs, ProviderSort,
+use crate::backend::{
+    ApiError, ChatCompletionRequest, ContentStream, LlmBackend, Message, MessageRole, StreamDelta,
+    Usage,
 };
+use crate::db::{CachedContent, Generation};
+use crate::facts::{self, StreamFilter};
+use crate::in_flight::{Flight, FollowOutcome, Leader};
+use crate::prefetch::Speculative;
+use crate::prompt::PromptContext;
+use crate::retry::{self, RetryPolicy};
 use crate::state::AppState;
 use crate::utils::normalize_path;
+use crate::{budget, conditional, content_type, versions};
 
 /// Creates a minijinja environment with error page templates
 fn create_template_env() -> Environment<'static> {
@@ -32,12 +44,42 @@ fn create_template_env() -> Environment<'static> {
     env
 }
 
-/// Determines the content type based on the request method, Accept header, and path.
+/// Determines the content type for a request, with the overrides of the first route matching its path applied.
+/// Routes that set a content type serve it without negotiating one.
 fn determine_content_type<'a>(
     method: &Method,
     headers: &HeaderMap,
     path: &str,
     state: &'a AppState,
+) -> Result<(&'a str, &'a crate::config::ContentTypeConfig), Box<Response>> {
+    let Some(route) = state.routes.find(path) else {
+        return negotiate_content_type(method, headers, path, state);
+    };
+    info!(route = %route.pattern, "Matched route");
+
+    let mime_type = match &route.mime_type {
+        Some(mime_type) => mime_type.as_str(),
+        None => negotiate_content_type(method, headers, path, state)?.0,
+    };
+    // Routes are compiled with every configured content type
+    match route.content_type(mime_type) {
+        Some((mime, ct)) => Ok((mime.as_str(), ct)),
+        None => Err(Box::new(
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Content type not configured for route",
+            )
+                .into_response(),
+        )),
+    }
+}
+
+/// Determines the content type based on the request method, Accept header, and path.
+fn negotiate_content_type<'a>(
+    method: &Method,
+    headers: &HeaderMap,
+    path: &str,
+    state: &'a AppState,
 ) -> Result<(&'a str, &'a crate::config::ContentTypeConfig), Box<Response>> {
     if method == Method::POST {
         // For POST requests, always generate JSON regardless of path
@@ -60,13 +102,18 @@ fn determine_content_type<'a>(
             }
         }
     } else {
-        // For GET and other requests, check Accept header first, then fall back to path
-        let accept_header = headers.get("accept").and_then(|v| v.to_str().ok());
+        // For GET and other requests, negotiate between the Accept header and the type the path maps to
+        let accept_header = headers.get(header::ACCEPT).and_then(|v| v.to_str().ok());
 
-        match content_type::determine_from_accept(accept_header, &state.config)
-            .or_else(|| content_type::determine_from_path(path, &state.config))
-        {
+        match content_type::negotiate(accept_header, path, &state.config) {
             Some((mime, ct)) => Ok((mime.as_str(), ct)),
+            None if content_type::determine_from_path(path, &state.config).is_some() => {
+                // The path's type is configured, but the client accepts neither it nor any other type
+                info!(accept = %accept_header.unwrap_or(""), "No acceptable content type");
+                Err(Box::new(
+                    (StatusCode::NOT_ACCEPTABLE, "Not Acceptable").into_response(),
+                ))
+            }
             None => {
                 // Unsupported file extension and no Accept header match
                 Err(Box::new(
@@ -77,16 +124,26 @@ fn determine_content_type<'a>(
     }
 }
 
-/// Builds reference materials from database-stored referer, base page, parent paths, and request body.
+/// Builds reference materials from database-stored referer, base page, parent paths, and the body of POST requests.
+/// Stored pages in the requested MIME type are preferred, falling back to other representations of the same path,
+/// and are left out entirely if the content type turns reference materials off.
 async fn build_reference_materials(
     state: &AppState,
     referer: &str,
     uri: &Uri,
     path: &str,
-    method: &Method,
-    body_str: &str,
+    mime_type: &str,
+    content_type: &crate::config::ContentTypeConfig,
+    post_body: Option<&str>,
 ) -> String {
     let mut reference_materials = String::new();
+    if !content_type.reference_materials {
+        info!("Reference materials turned off");
+        return post_body
+            .filter(|body| !body.is_empty())
+            .map(|body| format!("## Request Body\n\n{}", body))
+            .unwrap_or_default();
+    }
 
     // Build reference materials from database-stored referer if available
     if !referer.is_empty() {
@@ -95,7 +152,16 @@ async fn build_reference_materials(
             let referer_path = normalize_path(referer_url.path());
             let referer_query = referer_url.query().unwrap_or("");
 
-            if let Ok(Some(referer_content)) = state.db.get(referer_path, referer_query).await {
+            // The referer is usually a page, so prefer the type its own path maps to
+            let referer_mime_type = content_type::determine_from_path(referer_path, &state.config)
+                .map(|(mime, _)| mime.as_str())
+                .unwrap_or(mime_type);
+
+            if let Ok(Some(referer_content)) = state
+                .db
+                .get_reference(referer_path, referer_query, referer_mime_type)
+                .await
+            {
                 reference_materials.push_str("### ");
                 reference_materials.push_str(referer_path);
                 reference_materials.push_str("\n\n");
@@ -109,7 +175,7 @@ async fn build_reference_materials(
     // e.g., for /apples?color=green, include /apples if available
     if let Some(query_str) = uri.query()
         && !query_str.is_empty()
-        && let Ok(Some(base_content)) = state.db.get(path, "").await
+        && let Ok(Some(base_content)) = state.db.get_reference(path, "", mime_type).await
     {
         if !reference_materials.is_empty() {
             reference_materials.push_str("\n\n");
@@ -131,7 +197,9 @@ async fn build_reference_materials(
         for i in 1..path_segments.len() {
             let parent_path = format!("/{}", path_segments[..i].join("/"));
 
-            if let Ok(Some(parent_content)) = state.db.get(&parent_path, "").await {
+            if let Ok(Some(parent_content)) =
+                state.db.get_reference(&parent_path, "", mime_type).await
+            {
                 if !reference_materials.is_empty() {
                     reference_materials.push_str("\n\n");
                 } else {
@@ -147,24 +215,38 @@ async fn build_reference_materials(
     }
 
     // For POST requests, include the request body in reference materials
-    if method == Method::POST && !body_str.is_empty() {
+    if let Some(body) = post_body
+        && !body.is_empty()
+    {
         if !reference_materials.is_empty() {
             reference_materials.push_str("\n\n");
         }
         reference_materials.push_str("## Request Body\n\n");
-        reference_materials.push_str(body_str);
+        reference_materials.push_str(body);
     }
 
     reference_materials
 }
 
-/// Checks the database for GET requests and returns stored content if available.
+/// Whether the request asks for fresh content with `Cache-Control: no-cache`, as browsers send on a hard reload.
+fn requests_regeneration(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::CACHE_CONTROL)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|directive| directive.trim().eq_ignore_ascii_case("no-cache"))
+}
+
+/// Checks the database for GET requests and returns stored content if available and not expired.
 async fn check_cache(
     state: &AppState,
     method: &Method,
+    headers: &HeaderMap,
     path: &str,
     uri: &Uri,
-    content_type_header: &str,
+    mime_type: &str,
+    content_type: &crate::config::ContentTypeConfig,
 ) -> Result<Option<Response>, Response> {
     if method != Method::GET {
         return Ok(None);
@@ -172,12 +254,19 @@ async fn check_cache(
 
     let query = uri.query().unwrap_or("");
 
-    match state.db.get(path, query).await {
-        Ok(Some(content)) => {
+    match state
+        .db
+        .get(path, query, mime_type, content_type.ttl())
+        .await
+    {
+        // Imported content is served even on a hard reload, as it is never regenerated
+        Ok(Some(cached)) if !cached.authored && requests_regeneration(headers) => {
+            info!("Regeneration requested with Cache-Control: no-cache");
+            Ok(None)
+        }
+        Ok(Some(cached)) => {
             info!(query = %query, "Database hit");
-            Ok(Some(
-                ([("Content-Type", content_type_header)], content).into_response(),
-            ))
+            Ok(Some(cached_response(headers, content_type, cached)))
         }
         Ok(None) => {
             info!(query = %query, "Database miss");
@@ -191,63 +280,261 @@ async fn check_cache(
     }
 }
 
-/// Checks if the request is already in-flight and returns an error response if so.
-async fn check_in_flight(
+/// Responds with cached content along with its validators, or with `304 Not Modified` if the
+/// request's conditional headers show the client already has it.
+fn cached_response(
+    headers: &HeaderMap,
+    content_type: &crate::config::ContentTypeConfig,
+    cached: CachedContent,
+) -> Response {
+    let etag = conditional::etag(&cached.content);
+    let mut response_headers = HeaderMap::new();
+    if let Ok(value) = HeaderValue::from_str(&etag) {
+        response_headers.insert(header::ETAG, value);
+    }
+    if let Some(last_modified) = cached.last_modified
+        && let Ok(value) = HeaderValue::from_str(&httpdate::fmt_http_date(last_modified))
+    {
+        response_headers.insert(header::LAST_MODIFIED, value);
+    }
+    if let Some(cache_control) = &content_type.cache_control
+        && let Ok(value) = HeaderValue::from_str(cache_control)
+    {
+        response_headers.insert(header::CACHE_CONTROL, value);
+    }
+
+    if conditional::is_not_modified(headers, &etag, cached.last_modified) {
+        info!("Not modified");
+        return (StatusCode::NOT_MODIFIED, response_headers).into_response();
+    }
+
+    (
+        response_headers,
+        [("Content-Type", content_type.content_type_header.as_str())],
+        cached.content,
+    )
+        .into_response()
+}
+
+/// Serves the stored version of a resource requested with the version query parameter.
+/// Returns `None` if no version was requested.
+async fn check_version(
     state: &AppState,
     method: &Method,
-    path_and_query: &str,
-) -> Result<(), Response> {
+    path: &str,
+    uri: &Uri,
+    mime_type: &str,
+    content_type: &crate::config::ContentTypeConfig,
+) -> Option<Response> {
     if method != Method::GET {
-        return Ok(());
+        return None;
     }
 
-    let is_in_flight = {
-        let in_flight = state.in_flight.read().await;
-        in_flight.contains(path_and_query)
+    let (version, query) = match versions::split_version_param(uri.query().unwrap_or("")) {
+        Ok((Some(version), query)) => (version, query),
+        Ok((None, _)) => return None,
+        Err(e) => return Some((StatusCode::BAD_REQUEST, e.to_string()).into_response()),
     };
 
-    if is_in_flight {
-        info!("Request already in-flight, returning 503 Service Unavailable");
-        return Err((
-            StatusCode::SERVICE_UNAVAILABLE,
-            [("Retry-After", "1")],
-            "Content generation in progress. Please retry shortly.",
-        )
-            .into_response());
+    match state.db.get_version(path, &query, mime_type, version).await {
+        Ok(Some(content)) => {
+            info!(version = %version, "Serving stored version");
+            Some(
+                (
+                    [("Content-Type", content_type.content_type_header.as_str())],
+                    content,
+                )
+                    .into_response(),
+            )
+        }
+        Ok(None) => Some((StatusCode::NOT_FOUND, "Version not found").into_response()),
+        Err(e) => {
+            warn!(version = %version, error = %e, "Failed to read stored version");
+            Some(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Failed to read stored version",
+                )
+                    .into_response(),
+            )
+        }
     }
+}
+
+/// Response for requests that gave up waiting on another request's generation
+fn in_flight_unavailable() -> Response {
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        [("Retry-After", "1")],
+        "Content generation in progress. Please retry shortly.",
+    )
+        .into_response()
+}
 
-    Ok(())
+/// Returns the response to send instead of generating content if a spending budget is exhausted.
+async fn check_budget(state: &AppState) -> Option<Response> {
+    let budget = state.config.budget.as_ref()?;
+
+    match budget::exhausted_period(&state.db, budget).await {
+        Ok(None) => None,
+        Ok(Some(period)) => {
+            warn!(period = %period, "Budget exhausted, not generating content");
+            Some(
+                (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    axum::response::Html(budget.exhausted_page.clone()),
+                )
+                    .into_response(),
+            )
+        }
+        Err(e) => {
+            warn!(error = %e, "Failed to check budget");
+            Some((StatusCode::INTERNAL_SERVER_ERROR, "Failed to check budget").into_response())
+        }
+    }
 }
 
-/// Registers the request as in-flight for GET requests.
-/// Returns true if successfully registered, false if not applicable (non-GET requests).
-async fn register_in_flight(
+/// Joins the in-flight generation of the requested representation for GET requests.
+/// Returns a leader handle if this request should generate the content, or `None` for non-GET requests.
+/// Returns the response to send if another request was already generating the same path,
+/// either its shared content, with the same headers as a cache hit, or a 503 if it failed or did not finish in time.
+async fn join_in_flight(
     state: &AppState,
     method: &Method,
+    headers: &HeaderMap,
     path_and_query: &str,
-) -> Result<bool, Response> {
+    mime_type: &str,
+    content_type: &crate::config::ContentTypeConfig,
+) -> Result<Option<Leader>, Response> {
     if method != Method::GET {
-        return Ok(false);
+        return Ok(None);
     }
 
-    let mut in_flight = state.in_flight.write().await;
+    let key = format!("{} {}", mime_type, path_and_query);
+    let follower = match state.in_flight.join(&key) {
+        Flight::Leader(leader) => {
+            info!("Registered as in-flight");
+            return Ok(Some(leader));
+        }
+        Flight::Follower(follower) => follower,
+    };
+
+    info!("Request already in-flight, waiting for it to complete");
+    let timeout = Duration::from_secs(state.config.in_flight_timeout_secs);
 
-    // Double-check that another request didn't register while we were acquiring the write lock
-    if in_flight.contains(path_and_query) {
-        drop(in_flight); // Release the write lock
+    match follower.wait(timeout).await {
+        FollowOutcome::Completed(cached) => {
+            info!(bytes = %cached.content.len(), "Serving content from in-flight request");
+            Err(cached_response(headers, content_type, (*cached).clone()))
+        }
+        FollowOutcome::Failed => {
+            info!("In-flight request failed, returning 503 Service Unavailable");
+            Err(in_flight_unavailable())
+        }
+        FollowOutcome::TimedOut => {
+            info!(
+                timeout_secs = %timeout.as_secs(),
+                "Timed out waiting for in-flight request, returning 503 Service Unavailable"
+            );
+            Err(in_flight_unavailable())
+        }
+    }
+}
 
-        info!("Request became in-flight while acquiring lock, returning 503 Service Unavailable");
-        return Err((
-            StatusCode::SERVICE_UNAVAILABLE,
-            [("Retry-After", "1")],
-            "Content generation in progress. Please retry shortly.",
-        )
-            .into_response());
+/// Separates the facts block from generated content if the site records facts, storing the facts it lists.
+/// Returns the content to serve.
+async fn record_facts(state: &AppState, path: &str, content: String) -> String {
+    if !state.config.records_facts() {
+        return content;
     }
 
-    in_flight.insert(path_and_query.to_string());
-    info!("Registered as in-flight");
-    Ok(true)
+    let (served, facts) = facts::split(&content);
+    if !facts.is_empty() {
+        match state.db.add_facts(path, &facts).await {
+            Ok(added) => info!(facts = %facts.len(), added = %added, "Recorded facts"),
+            Err(e) => warn!(error = %e, "Failed to record facts"),
+        }
+    }
+    if served.len() == content.len() {
+        content
+    } else {
+        served.to_string()
+    }
+}
+
+/// Stores generated content in the database for GET requests, and records its usage for all requests.
+/// Returns when the content was stored, or `None` if it wasn't stored or isn't the version served.
+async fn store_generation(
+    state: &AppState,
+    method: &Method,
+    path: &str,
+    query: &str,
+    content: &str,
+    generation: &Generation,
+) -> Option<SystemTime> {
+    let usage = &generation.usage;
+    info!(
+        prompt_tokens = %usage.prompt_tokens,
+        completion_tokens = %usage.completion_tokens,
+        cost = %usage.cost.map(|c| format!("{:.6}", c)).unwrap_or_default(),
+        "Generation usage"
+    );
+
+    if method != Method::GET {
+        if let Err(e) = state.db.record_usage(path, query, generation).await {
+            info!(query = %query, error = %e, "Failed to record usage in database");
+        }
+        return None;
+    }
+
+    match state.db.set(path, query, content, generation).await {
+        Ok(stored_at) => {
+            info!(query = %query, "Stored generation in database");
+            stored_at
+        }
+        Err(e) => {
+            info!(query = %query, error = %e, "Failed to store generation in database");
+            // Continue serving the response even if storing fails
+            None
+        }
+    }
+}
+
+/// Maps a generation error to the status reported to the client.
+fn api_error_status(e: &anyhow::Error) -> StatusCode {
+    match e.downcast_ref::<ApiError>() {
+        Some(ApiError::Auth(_)) => StatusCode::UNAUTHORIZED,
+        Some(ApiError::RateLimited { .. }) => StatusCode::TOO_MANY_REQUESTS,
+        Some(ApiError::Timeout(_)) => StatusCode::GATEWAY_TIMEOUT,
+        _ => StatusCode::BAD_GATEWAY,
+    }
+}
+
+/// Logs an API error and renders it as an error page with a matching status.
+fn api_error_response(env: &Environment<'_>, e: &anyhow::Error, duration: Duration) -> Response {
+    // Log error with full chain of causes
+    let error_chain: Vec<String> = e.chain().map(|e| e.to_string()).collect();
+    let error_msg = error_chain.join("\n  caused by: ");
+
+    warn!(
+        duration_secs = %format!("{:.2}", duration.as_secs_f64()),
+        error = %error_msg,
+        "API error"
+    );
+
+    let error_html = env
+        .get_template("api_error")
+        .and_then(|tmpl| tmpl.render(minijinja::context! { error => e.to_string() }))
+        .unwrap_or_else(|_| format!("<h1>Error generating page</h1><p>{}</p>", e));
+
+    let mut response = (api_error_status(e), axum::response::Html(error_html)).into_response();
+    if let Some(retry_after) = e.downcast_ref::<ApiError>().and_then(ApiError::retry_after) {
+        response.headers_mut().insert(
+            header::RETRY_AFTER,
+            HeaderValue::from(retry_after.as_secs().max(1)),
+        );
+    }
+    response
 }
 
 /// Parameters for content generation
@@ -260,30 +547,177 @@ struct GenerateParams<'a> {
     method: &'a Method,
     path: &'a str,
     uri: &'a Uri,
+    headers: &'a HeaderMap,
+    /// Request body, given to user prompt templates
+    body: &'a str,
+    /// Set when this request leads an in-flight generation that followers are waiting on
+    in_flight: Option<Leader>,
+    /// Whether the request was made by the prefetcher rather than a client
+    speculative: bool,
+}
+
+impl GenerateParams<'_> {
+    /// Describes a successful generation by `model` for storing alongside its content
+    fn generation(&self, model: String, duration: Duration) -> Generation {
+        Generation {
+            model,
+            mime_type: self.mime_type.to_string(),
+            system_prompt_hash: self.content_type.system_prompt_hash(),
+            method: self.method.to_string(),
+            status: StatusCode::OK.as_u16(),
+            duration,
+            usage: Usage::default(),
+            speculative: self.speculative,
+        }
+    }
+
+    /// Number of links to prefetch from the generated content, which is only done for HTML
+    /// requested by clients so prefetched pages don't set off further prefetches
+    fn prefetch_links(&self) -> usize {
+        if *self.method == Method::GET && self.mime_type == "text/html" && !self.speculative {
+            self.content_type.prefetch_links
+        } else {
+            0
+        }
+    }
 }
 
-/// Generates content using the OpenAI API and stores it in the database for GET requests.
+/// Forwards streamed content to the client as a chunked body from a background task.
+/// The full content is only stored once the stream completes; aborted or errored streams are discarded.
+fn forward_stream(
+    state: &Arc<AppState>,
+    mut stream: ContentStream,
+    model: String,
+    params: &mut GenerateParams<'_>,
+    start: Instant,
+) -> std::io::Result<Response> {
+    let (tx, mut rx) = mpsc::channel::<Result<Bytes, std::io::Error>>(32);
+
+    let state = Arc::clone(state);
+    let method = params.method.clone();
+    let path = params.path.to_string();
+    let query = params.uri.query().unwrap_or("").to_string();
+    let mut generation = params.generation(model, Duration::ZERO);
+    let leader = params.in_flight.take();
+    let path_and_query = params.path_and_query.to_string();
+    let prefetch_links = params.prefetch_links();
+    // Facts the model records are kept out of what the client is sent
+    let mut filter = state.config.records_facts().then(StreamFilter::default);
+
+    let task = async move {
+        let mut content = String::new();
+        let usage = &mut generation.usage;
+
+        let completed = loop {
+            match stream.next().await {
+                Some(Ok(StreamDelta::Usage(reported))) => usage.merge(reported),
+                Some(Ok(StreamDelta::Content(delta))) => {
+                    content.push_str(&delta);
+                    let delta = match &mut filter {
+                        Some(filter) => filter.push(&delta),
+                        None => delta,
+                    };
+                    if !delta.is_empty() && tx.send(Ok(Bytes::from(delta))).await.is_err() {
+                        info!(
+                            bytes = %content.len(),
+                            "Client disconnected, discarding partial generation"
+                        );
+                        break false;
+                    }
+                }
+                Some(Err(e)) => {
+                    warn!(
+                        duration_secs = %format!("{:.2}", start.elapsed().as_secs_f64()),
+                        bytes = %content.len(),
+                        error = %e,
+                        "API stream error, discarding partial generation"
+                    );
+                    // Abort the chunked body so the client sees an incomplete response
+                    let _ = tx.send(Err(std::io::Error::other(e.to_string()))).await;
+                    break false;
+                }
+                None => break true,
+            }
+        };
+
+        if completed {
+            if let Some(rest) = filter.map(StreamFilter::finish)
+                && !rest.is_empty()
+            {
+                let _ = tx.send(Ok(Bytes::from(rest))).await;
+            }
+
+            generation.duration = start.elapsed();
+            info!(
+                duration_secs = %format!("{:.2}", generation.duration.as_secs_f64()),
+                bytes = %content.len(),
+                content_type = %generation.mime_type,
+                model = %generation.model,
+                "API stream completed"
+            );
+            let content = record_facts(&state, &path, content).await;
+            let stored_at =
+                store_generation(&state, &method, &path, &query, &content, &generation).await;
+            state
+                .prefetcher
+                .enqueue_links(&path_and_query, &content, prefetch_links);
+
+            if let Some(leader) = leader {
+                leader.complete(CachedContent {
+                    content,
+                    last_modified: stored_at,
+                    authored: false,
+                });
+            }
+        }
+    };
+
+    tokio::task::Builder::new()
+        .name("stream-generation")
+        .spawn(task.instrument(tracing::Span::current()))?;
+
+    let body = Body::from_stream(stream::poll_fn(move |cx| rx.poll_recv(cx)));
+
+    Ok((
+        [(
+            "Content-Type",
+            params.content_type.content_type_header.as_str(),
+        )],
+        body,
+    )
+        .into_response())
+}
+
+/// Generates content using the content type's backend and stores it in the database for GET requests.
 async fn generate_content(
-    state: &AppState,
-    client: &OpenRouterClient,
-    params: GenerateParams<'_>,
+    state: &Arc<AppState>,
+    backend: &dyn LlmBackend,
+    mut params: GenerateParams<'_>,
 ) -> Response {
     let env = create_template_env();
 
     // Build user prompt with error handling
-    let mut prompt_builder = params
-        .content_type
-        .user_prompt_builder(params.path_and_query.to_string());
-
-    if !params.referer.is_empty() {
-        prompt_builder = prompt_builder.headers(params.referer.to_string());
-    }
-
-    if !params.reference_materials.is_empty() {
-        prompt_builder = prompt_builder.reference_materials(params.reference_materials.to_string());
+    let method = params.method.as_str();
+    let mut context =
+        PromptContext::new(params.path_and_query, params.path, method, params.mime_type)
+            .headers(params.headers);
+    context.referer = params.referer;
+    context.reference_materials = params.reference_materials;
+    context.body = params.body;
+
+    if let Some(world) = &state.config.world {
+        let facts = if world.facts {
+            state.db.facts(world.max_facts).await.unwrap_or_else(|e| {
+                warn!(error = %e, "Failed to read facts");
+                Vec::new()
+            })
+        } else {
+            Vec::new()
+        };
+        context = context.world(world, facts);
     }
 
-    let user_prompt = match prompt_builder.build() {
+    let user_prompt = match state.prompts.render(params.mime_type, &context) {
         Ok(prompt) => prompt,
         Err(e) => {
             info!(error = %e, "Failed to render user prompt template");
@@ -296,7 +730,11 @@ async fn generate_content(
                         e
                     )
                 });
-            return axum::response::Html(error_html).into_response();
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                axum::response::Html(error_html),
+            )
+                .into_response();
         }
     };
 
@@ -312,20 +750,36 @@ async fn generate_content(
                 content: user_prompt,
             },
         ],
-        provider: Some(ProviderPrefs {
-            sort: ProviderSort::Latency,
-        }),
+        stream: false,
+        path: params.path_and_query.to_string(),
+        mime_type: params.mime_type.to_string(),
     };
 
-    let start = std::time::Instant::now();
+    let policy = RetryPolicy::from_config(params.content_type);
+    let start = Instant::now();
     info!(
-        model = %params.content_type.model,
+        backend = %params.content_type.backend,
+        models = %policy.models.join(", "),
         content_type = %params.mime_type,
+        stream = %params.content_type.stream,
         "Calling API"
     );
 
-    match client.chat_completion(request).await {
-        Ok(response) => {
+    if params.content_type.stream {
+        let result = retry::chat_completion_stream(backend, &policy, request)
+            .await
+            .and_then(|(model, stream)| {
+                Ok(forward_stream(state, stream, model, &mut params, start)?)
+            });
+
+        return match result {
+            Ok(response) => response,
+            Err(e) => api_error_response(&env, &e, start.elapsed()),
+        };
+    }
+
+    match retry::chat_completion(backend, &policy, request).await {
+        Ok((model, response)) => {
             let duration = start.elapsed();
             let content = response
                 .choices
@@ -337,22 +791,35 @@ async fn generate_content(
                 duration_secs = %format!("{:.2}", duration.as_secs_f64()),
                 bytes = %content.len(),
                 content_type = %params.mime_type,
+                model = %model,
                 "API responded"
             );
 
-            // Save to database only for GET requests
-            if params.method == Method::GET {
-                let query = params.uri.query().unwrap_or("");
+            let content = record_facts(state, params.path, content).await;
+            let mut generation = params.generation(model, duration);
+            generation.usage = response.usage.unwrap_or_default();
+            let query = params.uri.query().unwrap_or("");
+            let stored_at = store_generation(
+                state,
+                params.method,
+                params.path,
+                query,
+                &content,
+                &generation,
+            )
+            .await;
+            state.prefetcher.enqueue_links(
+                params.path_and_query,
+                &content,
+                params.prefetch_links(),
+            );
 
-                match state.db.set(params.path, query, &content).await {
-                    Ok(_) => {
-                        info!(query = %query, "Stored generation in database");
-                    }
-                    Err(e) => {
-                        info!(query = %query, error = %e, "Failed to store generation in database");
-                        // Continue serving the response even if storing fails
-                    }
-                }
+            if let Some(leader) = params.in_flight.take() {
+                leader.complete(CachedContent {
+                    content: content.clone(),
+                    last_modified: stored_at,
+                    authored: false,
+                });
             }
 
             (
@@ -364,25 +831,7 @@ async fn generate_content(
             )
                 .into_response()
         }
-        Err(e) => {
-            let duration = start.elapsed();
-
-            // Log error with full chain of causes
-            let error_chain: Vec<String> = e.chain().map(|e| e.to_string()).collect();
-            let error_msg = error_chain.join("\n  caused by: ");
-
-            warn!(
-                duration_secs = %format!("{:.2}", duration.as_secs_f64()),
-                error = %error_msg,
-                "API error"
-            );
-
-            let error_html = env
-                .get_template("api_error")
-                .and_then(|tmpl| tmpl.render(minijinja::context! { error => e.to_string() }))
-                .unwrap_or_else(|_| format!("<h1>Error generating page</h1><p>{}</p>", e));
-            axum::response::Html(error_html).into_response()
-        }
+        Err(e) => api_error_response(&env, &e, start.elapsed()),
     }
 }
 
@@ -391,6 +840,7 @@ pub async fn handle(State(state): State<Arc<AppState>>, req: Request) -> impl In
     let uri = req.uri().clone();
     let method = req.method().clone();
     let headers = req.headers().clone();
+    let speculative = req.extensions().get::<Speculative>().is_some();
 
     let path_and_query = uri.path_and_query().unwrap().as_str();
     let path = normalize_path(uri.path());
@@ -420,17 +870,41 @@ pub async fn handle(State(state): State<Arc<AppState>>, req: Request) -> impl In
         Err(response) => return *response,
     };
 
+    // Serve a stored version if one was requested, without generating anything
+    if let Some(response) =
+        check_version(&state, &method, path, &uri, mime_type, content_type).await
+    {
+        return response;
+    }
+
+    // Backends are validated against content types at startup
+    let Some(backend) = state.backends.get(&content_type.backend).cloned() else {
+        warn!(backend = %content_type.backend, "Backend not configured");
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Backend not configured").into_response();
+    };
+
     // Build reference materials from database-stored referer, base page, parent paths, and request body
-    let reference_materials =
-        build_reference_materials(&state, referer, &uri, path, &method, &body_str).await;
+    let post_body = (method == Method::POST).then_some(body_str.as_str());
+    let reference_materials = build_reference_materials(
+        &state,
+        referer,
+        &uri,
+        path,
+        mime_type,
+        content_type,
+        post_body,
+    )
+    .await;
 
     // Check database for GET requests
     if let Some(cached_response) = check_cache(
         &state,
         &method,
+        &headers,
         path,
         &uri,
-        &content_type.content_type_header,
+        mime_type,
+        content_type,
     )
     .await
     .unwrap_or(None)
@@ -438,21 +912,31 @@ pub async fn handle(State(state): State<Arc<AppState>>, req: Request) -> impl In
         return cached_response;
     }
 
-    // Check if this path is already being generated by another request
-    if let Err(response) = check_in_flight(&state, &method, path_and_query).await {
+    // Serve only cached content once a spending budget is exhausted
+    if let Some(response) = check_budget(&state).await {
         return response;
     }
 
-    // For GET requests, register this request as in-flight
-    let is_registered = match register_in_flight(&state, &method, path_and_query).await {
-        Ok(registered) => registered,
+    // For GET requests, lead the generation for this path or wait on the request already generating it
+    let leader = match join_in_flight(
+        &state,
+        &method,
+        &headers,
+        path_and_query,
+        mime_type,
+        content_type,
+    )
+    .await
+    {
+        Ok(leader) => leader,
         Err(response) => return response,
     };
 
-    // Generate content using the shared OpenRouter client
-    let result = generate_content(
+    // Generate content using the content type's backend.
+    // The in-flight leader is released once generation ends, which may be after a streamed response is returned.
+    generate_content(
         &state,
-        &state.openrouter_client,
+        backend.as_ref(),
         GenerateParams {
             content_type,
             mime_type,
@@ -462,16 +946,11 @@ pub async fn handle(State(state): State<Arc<AppState>>, req: Request) -> impl In
             method: &method,
             path,
             uri: &uri,
+            headers: &headers,
+            body: &body_str,
+            in_flight: leader,
+            speculative,
         },
     )
-    .await;
-
-    // Clean up in-flight tracking
-    if is_registered {
-        let mut in_flight = state.in_flight.write().await;
-        in_flight.remove(path_and_query);
-        info!("Removed from in-flight tracking");
-    }
-
-    result
+    .await
 }
@@ -0,0 +1,123 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use percent_encoding::{AsciiSet, CONTROLS, utf8_percent_encode};
+use tracing::warn;
+
+use crate::config::WebSimConfig;
+use crate::content_type;
+use crate::db::Database;
+
+/// Characters escaped in URL path segments built from file names
+const PATH_SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// A file imported as a resource
+#[derive(Debug)]
+pub struct ImportedFile {
+    pub file: PathBuf,
+    pub path: String,
+    pub mime_type: String,
+    pub version: u32,
+}
+
+/// Imports every file under `dir` as hand-written content, served at the path of the file relative to `dir`.
+/// MIME types are inferred from extensions as for requests, so files with extensions no content type is
+/// configured for are skipped, as are hidden files and files that aren't UTF-8 text.
+/// HTML index files are served at their directory's path, e.g. `about/index.html` at `/about`.
+pub async fn import_dir(
+    db: &Database,
+    config_path: &Path,
+    dir: &Path,
+) -> Result<Vec<ImportedFile>> {
+    let config = WebSimConfig::load(config_path)?.site(db.site())?;
+
+    let mut files = Vec::new();
+    collect_files(dir, &mut files)?;
+    files.sort();
+
+    let mut imported = Vec::new();
+    for file in files {
+        let relative = file.strip_prefix(dir)?;
+        let mut path = url_path(relative);
+        let Some((mime_type, _)) = content_type::determine_from_path(&path, &config) else {
+            warn!(file = %file.display(), "Skipping file with an extension no content type is configured for");
+            continue;
+        };
+
+        let content = match fs::read_to_string(&file) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::InvalidData => {
+                warn!(file = %file.display(), "Skipping file that isn't UTF-8 text");
+                continue;
+            }
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read {}", file.display()));
+            }
+        };
+
+        if mime_type == "text/html"
+            && relative.file_stem().is_some_and(|stem| stem == "index")
+            && let Some((dir_path, _)) = path.rsplit_once('/')
+        {
+            path = if dir_path.is_empty() {
+                "/".to_string()
+            } else {
+                dir_path.to_string()
+            };
+        }
+
+        let version = db.import(&path, "", mime_type, &content).await?;
+        imported.push(ImportedFile {
+            file,
+            path,
+            mime_type: mime_type.clone(),
+            version,
+        });
+    }
+
+    Ok(imported)
+}
+
+/// Adds the files under `dir` to `files`, skipping hidden files and directories
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    let entries = fs::read_dir(dir)
+        .with_context(|| format!("Failed to read directory: {}", dir.display()))?;
+
+    for entry in entries {
+        let entry = entry?;
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let path = entry.path();
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// URL path for a file relative to the imported directory, e.g. `/blog/my%20post.html` for `blog/my post.html`
+fn url_path(relative: &Path) -> String {
+    relative
+        .components()
+        .map(|component| {
+            let name = component.as_os_str().to_string_lossy();
+            format!("/{}", utf8_percent_encode(&name, PATH_SEGMENT))
+        })
+        .collect()
+}
@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::watch;
+
+use crate::db::CachedContent;
+
+/// Content produced by a completed generation as it was stored, shared with every waiting follower
+pub type SharedContent = Arc<CachedContent>;
+
+type Flights = Arc<Mutex<HashMap<String, watch::Receiver<Option<SharedContent>>>>>;
+
+/// Coalesces concurrent generations for the same key so only one request calls the API
+#[derive(Default)]
+pub struct InFlight {
+    flights: Flights,
+}
+
+/// Role of a request in a single-flight generation
+pub enum Flight {
+    /// This request generates the content and must complete or drop the handle
+    Leader(Leader),
+    /// Another request is already generating the content
+    Follower(Follower),
+}
+
+/// Outcome of waiting on another request's generation
+pub enum FollowOutcome {
+    Completed(SharedContent),
+    /// The leader's generation failed or was aborted
+    Failed,
+    TimedOut,
+}
+
+impl InFlight {
+    /// Joins the generation for `key`, becoming the leader if none is in flight
+    pub fn join(&self, key: &str) -> Flight {
+        let mut flights = self.flights.lock().unwrap();
+
+        if let Some(rx) = flights.get(key) {
+            return Flight::Follower(Follower { rx: rx.clone() });
+        }
+
+        let (tx, rx) = watch::channel(None);
+        flights.insert(key.to_string(), rx);
+
+        Flight::Leader(Leader {
+            key: key.to_string(),
+            tx,
+            flights: Arc::clone(&self.flights),
+        })
+    }
+}
+
+/// Handle held by the request generating content.
+/// Dropping it without calling [`Leader::complete`] signals failure to followers.
+pub struct Leader {
+    key: String,
+    tx: watch::Sender<Option<SharedContent>>,
+    flights: Flights,
+}
+
+impl Leader {
+    /// Shares the generated content with all followers
+    pub fn complete(self, content: CachedContent) {
+        // Followers may have given up already, in which case there are no receivers left
+        let _ = self.tx.send(Some(Arc::new(content)));
+    }
+}
+
+impl Drop for Leader {
+    fn drop(&mut self) {
+        self.flights.lock().unwrap().remove(&self.key);
+    }
+}
+
+/// Handle held by a request waiting on a leader's generation
+pub struct Follower {
+    rx: watch::Receiver<Option<SharedContent>>,
+}
+
+impl Follower {
+    /// Waits up to `timeout` for the leader's generation to finish
+    pub async fn wait(mut self, timeout: Duration) -> FollowOutcome {
+        match tokio::time::timeout(timeout, self.rx.wait_for(Option::is_some)).await {
+            Ok(Ok(content)) => match content.as_ref() {
+                Some(content) => FollowOutcome::Completed(Arc::clone(content)),
+                None => FollowOutcome::Failed,
+            },
+            Ok(Err(_)) => FollowOutcome::Failed,
+            Err(_) => FollowOutcome::TimedOut,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_followers_receive_leader_content() {
+        let in_flight = InFlight::default();
+
+        let Flight::Leader(leader) = in_flight.join("/apples") else {
+            panic!("first request should lead");
+        };
+        let Flight::Follower(follower) = in_flight.join("/apples") else {
+            panic!("second request should follow");
+        };
+        assert!(matches!(in_flight.join("/pears"), Flight::Leader(_)));
+
+        let waiting = tokio::spawn(follower.wait(Duration::from_secs(5)));
+        leader.complete(CachedContent {
+            content: "<h1>Apples</h1>".to_string(),
+            last_modified: None,
+            authored: false,
+        });
+
+        match waiting.await.unwrap() {
+            FollowOutcome::Completed(cached) => assert_eq!(cached.content, "<h1>Apples</h1>"),
+            _ => panic!("follower should receive the leader's content"),
+        }
+
+        // The key is released once the leader is done
+        assert!(matches!(in_flight.join("/apples"), Flight::Leader(_)));
+    }
+
+    #[tokio::test]
+    async fn test_followers_observe_failure_and_timeout() {
+        let in_flight = InFlight::default();
+
+        let Flight::Leader(leader) = in_flight.join("/apples") else {
+            panic!("first request should lead");
+        };
+        let Flight::Follower(follower) = in_flight.join("/apples") else {
+            panic!("second request should follow");
+        };
+        assert!(matches!(
+            follower.wait(Duration::from_millis(10)).await,
+            FollowOutcome::TimedOut
+        ));
+
+        let Flight::Follower(follower) = in_flight.join("/apples") else {
+            panic!("request should follow while leader is generating");
+        };
+        drop(leader);
+        assert!(matches!(
+            follower.wait(Duration::from_secs(5)).await,
+            FollowOutcome::Failed
+        ));
+    }
+}
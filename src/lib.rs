@@ -1,11 +1,43 @@
+mod admin;
+mod anthropic;
+mod backend;
+mod budget;
+mod conditional;
 mod config;
 mod content_type;
+mod crawl;
 mod db;
+mod eviction;
+mod export;
+mod facts;
+mod fixtures;
 mod handler;
+mod import;
+mod in_flight;
+mod links;
+mod mock;
+mod ollama;
+mod openai;
 mod openrouter;
+mod prefetch;
+mod prompt;
+mod retry;
+mod routes;
 mod server;
+mod sites;
 mod state;
+mod usage;
 mod utils;
+mod versions;
+mod warc;
 
 // Re-export public API
-pub use server::run_server;
+pub use crawl::{CrawlOptions, CrawledPage, crawl};
+pub use db::{Database, PurgeFilter, VersionInfo};
+pub use export::{MANIFEST_FILE, Manifest, ManifestEntry, export_site};
+pub use import::{ImportedFile, import_dir};
+pub use server::{BackendMode, build_app, run_server};
+pub use sites::open_site_database;
+pub use usage::{UsageReport, UsageTotals, usage_report};
+pub use versions::diff as diff_versions;
+pub use warc::{DEFAULT_BASE_URL, ImportedRecord, export_warc, import_warc};
@@ -0,0 +1,139 @@
+use std::sync::LazyLock;
+
+use regex::{Captures, Regex};
+use url::Url;
+
+use crate::utils::normalize_path;
+
+/// Base that pages are resolved against, only used to tell same-site links from others
+const SITE: &str = "http://websim.invalid";
+
+/// Link targets and asset references in HTML, with the tag name in group 1 and the URL in group 2, 3
+/// or 4 depending on quoting
+static TAG_URL: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r#"(?i)<(a|img|link|script|iframe|source)\b[^>]*?\s(?:href|src)\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'>]+))"#,
+    )
+    .unwrap()
+});
+
+/// `fetch()` calls with a literal URL, in group 1, 2 or 3 depending on quoting
+static FETCH_URL: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"\bfetch\(\s*(?:"([^"]*)"|'([^']*)'|`([^`$]*)`)"#).unwrap());
+
+/// How a page refers to another resource, which decides the representation to request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkKind {
+    /// An `<a>` link a reader can follow, whose type follows from its path
+    Navigation,
+    /// An image, stylesheet, script or frame the page loads, whose type follows from its path
+    Asset,
+    /// A URL passed to `fetch()`, expected to return JSON
+    Fetch,
+}
+
+/// A same-site resource referenced by a page
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Link {
+    /// Normalized path and query, e.g. `/fruits/apples?color=green`
+    pub path_and_query: String,
+    pub kind: LinkKind,
+}
+
+impl Link {
+    /// `Accept` header to request the linked resource with, if its path doesn't decide its type
+    pub fn accept(&self) -> Option<&'static str> {
+        match self.kind {
+            LinkKind::Navigation | LinkKind::Asset => None,
+            LinkKind::Fetch => Some("application/json"),
+        }
+    }
+}
+
+/// Finds the same-site resources referenced by content served at `page`, in order of first appearance.
+/// Links to other sites, fragments of the same page and non-HTTP schemes are left out.
+pub fn extract(content: &str, page: &str) -> Vec<Link> {
+    let Ok(base) = Url::parse(SITE).and_then(|site| site.join(page)) else {
+        return Vec::new();
+    };
+
+    let tag_urls = TAG_URL.captures_iter(content).filter_map(|caps| {
+        let kind = if caps[1].eq_ignore_ascii_case("a") {
+            LinkKind::Navigation
+        } else {
+            LinkKind::Asset
+        };
+        Some((quoted_url(&caps, 2)?, kind))
+    });
+    let fetch_urls = FETCH_URL
+        .captures_iter(content)
+        .filter_map(|caps| Some((quoted_url(&caps, 1)?, LinkKind::Fetch)));
+
+    let mut links: Vec<Link> = Vec::new();
+    for (url, kind) in tag_urls.collect::<Vec<_>>().into_iter().chain(fetch_urls) {
+        if url.is_empty() || url.starts_with('#') {
+            continue;
+        }
+        let Ok(target) = base.join(url) else {
+            continue;
+        };
+        if target.origin() != base.origin() {
+            continue;
+        }
+
+        let path = normalize_path(target.path());
+        let path_and_query = match target.query() {
+            Some(query) if !query.is_empty() => format!("{}?{}", path, query),
+            _ => path.to_string(),
+        };
+        if path_and_query != page && !links.iter().any(|l| l.path_and_query == path_and_query) {
+            links.push(Link {
+                path_and_query,
+                kind,
+            });
+        }
+    }
+
+    links
+}
+
+/// URL in whichever of the alternatively quoted groups from `first` on matched
+fn quoted_url<'h>(caps: &Captures<'h>, first: usize) -> Option<&'h str> {
+    caps.iter()
+        .skip(first)
+        .flatten()
+        .next()
+        .map(|url| url.as_str().trim())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extracts_same_site_links() {
+        let html = r##"<link rel="stylesheet" href="/style.css"><a class="x" href='../pears/'>Pears</a>
+            <img alt="apples" src=apples.svg><a href="/fruits/apples#top">Top</a><a href="#top">Top</a>
+            <a href="https://example.com/">Elsewhere</a><a href="mailto:a@example.com">Mail</a>
+            <a href="?page=2">Next</a><script src="/app.js"></script>
+            <script>fetch('/api/apples').then(r => r.json()); fetch(`/api/${id}`);</script>"##;
+
+        let links = extract(html, "/fruits/apples");
+        let paths: Vec<&str> = links.iter().map(|l| l.path_and_query.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                "/style.css",
+                "/pears",
+                "/fruits/apples.svg",
+                "/fruits/apples?page=2",
+                "/app.js",
+                "/api/apples"
+            ]
+        );
+        assert_eq!(links[0].kind, LinkKind::Asset);
+        assert_eq!(links[1].kind, LinkKind::Navigation);
+        assert_eq!(links[0].accept(), None);
+        assert_eq!(links[5].accept(), Some("application/json"));
+    }
+}
@@ -1,27 +1,392 @@
 use std::path::PathBuf;
+use std::time::{Duration, UNIX_EPOCH};
 
-use anyhow::Result;
-use clap::Parser;
+use anyhow::{Result, bail};
+use clap::{Parser, Subcommand};
 use tracing::info;
+use websim::{BackendMode, CrawlOptions, Database, PurgeFilter};
 
 #[derive(Parser, Debug)]
 #[command(name = "websim")]
 #[command(about = "AI-powered web simulator", long_about = None)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Path to SQLite database for caching (if not provided, uses in-memory database)
-    #[arg(long)]
+    #[arg(long, global = true)]
     db: Option<PathBuf>,
 
     /// Path to configuration file
-    #[arg(short, long, default_value = "websim.config.yml")]
+    #[arg(short, long, global = true, default_value = "websim.config.yml")]
     config: PathBuf,
+
+    /// Generate all content with the offline mock backend (no API key or network needed)
+    #[arg(long, global = true, conflicts_with_all = ["record", "replay"])]
+    mock: bool,
+
+    /// Record every API response to this fixture file
+    #[arg(long, global = true, value_name = "FILE", conflicts_with = "replay")]
+    record: Option<PathBuf>,
+
+    /// Serve API responses only from this fixture file, failing on requests that were never recorded
+    #[arg(long, global = true, value_name = "FILE")]
+    replay: Option<PathBuf>,
+
+    /// Name of the configured site a command works on, instead of the top-level site
+    #[arg(long, global = true, default_value = "")]
+    site: String,
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let args = Args::parse();
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Print token usage and cost totals recorded in the database
+    Report {
+        /// Number of path segments to group usage by
+        #[arg(long, default_value_t = 1)]
+        depth: usize,
+
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Invalidate stored resources so they are regenerated on their next request
+    #[command(group = clap::ArgGroup::new("filter").required(true).multiple(true))]
+    Purge {
+        /// Exact path, with every query variation unless --query is also set
+        #[arg(long, group = "filter")]
+        path: Option<String>,
+
+        /// Query string of the path, without the leading `?`
+        #[arg(long, requires = "path")]
+        query: Option<String>,
+
+        /// Path prefix, e.g. /blog for /blog and everything under it
+        #[arg(long, group = "filter")]
+        prefix: Option<String>,
+
+        /// Model the resources were generated with
+        #[arg(long, group = "filter")]
+        model: Option<String>,
+
+        /// MIME type of the resources
+        #[arg(long, group = "filter")]
+        mime_type: Option<String>,
+
+        /// Purge every stored resource
+        #[arg(long, group = "filter", conflicts_with_all = ["path", "prefix", "model", "mime_type"])]
+        all: bool,
+    },
+    /// Generate a page and, recursively, the pages and assets it links to
+    Crawl {
+        /// Path to start from, e.g. /blog
+        seed: String,
+
+        /// Number of links to follow away from the seed page
+        #[arg(long, default_value_t = 2)]
+        depth: usize,
+
+        /// Most pages to request, and so generate, in this run
+        #[arg(long, default_value_t = 100)]
+        max_pages: usize,
+
+        /// Pages to generate at once
+        #[arg(long, default_value_t = 4)]
+        concurrency: usize,
+    },
+    /// Write the stored site to a directory of static files, with links rewritten between them
+    Export {
+        /// Directory to write the site to
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Store the files in a directory as hand-written content, which is never regenerated or evicted
+    Import {
+        /// Directory to import, with files served at their paths relative to it
+        dir: PathBuf,
+    },
+    /// Inspect and manage stored versions of a resource
+    Versions {
+        #[command(subcommand)]
+        command: VersionsCommand,
+    },
+    /// Archive the stored site as WARC, or seed it from a WARC archive
+    Warc {
+        #[command(subcommand)]
+        command: WarcCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum WarcCommand {
+    /// Write every stored resource as a request and response record pair
+    Export {
+        /// WARC file to write, with each record gzipped if it ends in .gz
+        #[arg(long)]
+        out: PathBuf,
+
+        /// URL the archived site is attributed to
+        #[arg(long, default_value = websim::DEFAULT_BASE_URL)]
+        base_url: String,
+    },
+    /// Store the successful text responses in a WARC file as hand-written content
+    Import {
+        /// WARC file to read, optionally gzipped
+        file: PathBuf,
+
+        /// Host to import responses from, defaulting to the host of the first response
+        #[arg(long)]
+        host: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum VersionsCommand {
+    /// List the stored versions of a resource
+    List {
+        #[command(flatten)]
+        resource: ResourceArgs,
+    },
+    /// Show a unified diff between two versions of a resource
+    Diff {
+        #[command(flatten)]
+        resource: ResourceArgs,
+        from: u32,
+        to: u32,
+    },
+    /// Serve a version of a resource even after it is regenerated
+    Pin {
+        #[command(flatten)]
+        resource: ResourceArgs,
+        version: u32,
+    },
+    /// Go back to serving the latest version of a resource
+    Unpin {
+        #[command(flatten)]
+        resource: ResourceArgs,
+    },
+    /// Serve a version of a resource until it is next regenerated
+    Rollback {
+        #[command(flatten)]
+        resource: ResourceArgs,
+        version: u32,
+    },
+}
 
-    // Initialize tracing subscriber
+/// Identifies a stored resource
+#[derive(clap::Args, Debug)]
+struct ResourceArgs {
+    /// Path of the resource, e.g. /fruits/apples
+    path: String,
+
+    /// Query string of the resource, without the leading `?`
+    #[arg(long, default_value = "")]
+    query: String,
+
+    /// MIME type of the resource
+    #[arg(long, default_value = "text/html")]
+    mime_type: String,
+}
+
+/// Runs a subcommand against the database instead of starting the server
+async fn run_command(
+    command: Command,
+    db_path: Option<PathBuf>,
+    config_path: PathBuf,
+    site: String,
+    mode: BackendMode,
+) -> Result<()> {
+    let Some(db_path) = db_path else {
+        bail!("This command requires a database (--db)");
+    };
+    let exports = matches!(
+        command,
+        Command::Export { .. }
+            | Command::Warc {
+                command: WarcCommand::Export { .. }
+            }
+    );
+    if exports && !db_path.exists() {
+        bail!("Database not found: {}", db_path.display());
+    }
+    let open = || websim::open_site_database(db_path.clone(), &config_path, &site);
+
+    match command {
+        Command::Report { depth, json } => {
+            let report = websim::usage_report(&open()?, depth).await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                print!("{}", report);
+            }
+        }
+        Command::Purge {
+            path,
+            query,
+            prefix,
+            model,
+            mime_type,
+            all: _,
+        } => {
+            let purged = open()?
+                .purge(&PurgeFilter {
+                    path,
+                    query,
+                    prefix,
+                    model,
+                    mime_type,
+                })
+                .await?;
+            println!("Purged {} resources", purged);
+        }
+        Command::Crawl {
+            seed,
+            depth,
+            max_pages,
+            concurrency,
+        } => {
+            init_tracing();
+            let options = CrawlOptions {
+                depth,
+                max_pages,
+                concurrency,
+                site: (!site.is_empty()).then(|| site.clone()),
+            };
+            let pages =
+                websim::crawl(db_path.clone(), config_path.clone(), mode, &seed, &options).await?;
+            let failed = pages.iter().filter(|page| page.status >= 400).count();
+            println!("Crawled {} pages ({} failed)", pages.len(), failed);
+        }
+        Command::Export { out } => {
+            let manifest = websim::export_site(&open()?, &out).await?;
+            println!(
+                "Exported {} resources to {} (see {})",
+                manifest.resources.len(),
+                out.display(),
+                websim::MANIFEST_FILE
+            );
+        }
+        Command::Import { dir } => {
+            let imported = websim::import_dir(&open()?, &config_path, &dir).await?;
+            for file in &imported {
+                println!(
+                    "{} -> {} ({}, version {})",
+                    file.file.display(),
+                    file.path,
+                    file.mime_type,
+                    file.version
+                );
+            }
+            println!("Imported {} files", imported.len());
+        }
+        Command::Versions { command } => {
+            run_versions_command(&open()?, command).await?;
+        }
+        Command::Warc {
+            command: WarcCommand::Export { out, base_url },
+        } => {
+            let archived = websim::export_warc(&open()?, &out, &base_url).await?;
+            println!("Archived {} resources to {}", archived, out.display());
+        }
+        Command::Warc {
+            command: WarcCommand::Import { file, host },
+        } => {
+            let imported = websim::import_warc(&open()?, &file, host.as_deref()).await?;
+            for record in &imported {
+                println!(
+                    "{} ({}, version {})",
+                    record.uri, record.mime_type, record.version
+                );
+            }
+            println!("Imported {} responses", imported.len());
+        }
+    }
+
+    Ok(())
+}
+
+async fn run_versions_command(db: &Database, command: VersionsCommand) -> Result<()> {
+    match command {
+        VersionsCommand::List { resource: r } => {
+            let versions = db.versions(&r.path, &r.query, &r.mime_type).await?;
+            if versions.is_empty() {
+                bail!("No versions stored for {}", r.path);
+            }
+            for v in versions {
+                let created = v
+                    .created_at
+                    .map(|secs| {
+                        httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(secs as u64))
+                    })
+                    .unwrap_or_default();
+                let flags = [
+                    (v.current, "current"),
+                    (v.pinned, "pinned"),
+                    (v.authored, "imported"),
+                ]
+                .iter()
+                .filter(|(set, _)| *set)
+                .map(|(_, flag)| *flag)
+                .collect::<Vec<_>>()
+                .join(", ");
+                println!(
+                    "{:>4}  {:<29}  {:<40}  {:>8} bytes  {}",
+                    v.version,
+                    created,
+                    v.model.unwrap_or_default(),
+                    v.bytes,
+                    flags
+                );
+            }
+        }
+        VersionsCommand::Diff {
+            resource: r,
+            from,
+            to,
+        } => {
+            let get = |version| db.get_version(&r.path, &r.query, &r.mime_type, version);
+            let (Some(from_content), Some(to_content)) = (get(from).await?, get(to).await?) else {
+                bail!("Version not found for {}", r.path);
+            };
+            print!(
+                "{}",
+                websim::diff_versions(&r.path, (from, &from_content), (to, &to_content))
+            );
+        }
+        VersionsCommand::Pin {
+            resource: r,
+            version,
+        } => {
+            if !db.pin(&r.path, &r.query, &r.mime_type, version).await? {
+                bail!("Version {} not found for {}", version, r.path);
+            }
+            println!("Pinned {} to version {}", r.path, version);
+        }
+        VersionsCommand::Unpin { resource: r } => {
+            if !db.unpin(&r.path, &r.query, &r.mime_type).await? {
+                bail!("No versions stored for {}", r.path);
+            }
+            println!("Serving the latest version of {}", r.path);
+        }
+        VersionsCommand::Rollback {
+            resource: r,
+            version,
+        } => {
+            if !db
+                .rollback(&r.path, &r.query, &r.mime_type, version)
+                .await?
+            {
+                bail!("Version {} not found for {}", version, r.path);
+            }
+            println!("Rolled back {} to version {}", r.path, version);
+        }
+    }
+
+    Ok(())
+}
+
+/// Logs to stdout, filtered by `RUST_LOG` (defaulting to info), and to tokio-console if `TOKIO_CONSOLE` is set
+fn init_tracing() {
     let env_filter = tracing_subscriber::EnvFilter::try_from_default_env()
         .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"));
 
@@ -50,6 +415,26 @@ async fn main() -> Result<()> {
             .with_span_events(tracing_subscriber::fmt::format::FmtSpan::NONE)
             .init();
     }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+
+    let mode = if args.mock {
+        BackendMode::Mock
+    } else if let Some(path) = args.record {
+        BackendMode::Record(path)
+    } else if let Some(path) = args.replay {
+        BackendMode::Replay(path)
+    } else {
+        BackendMode::Configured
+    };
+
+    if let Some(command) = args.command {
+        return run_command(command, args.db, args.config, args.site, mode).await;
+    }
 
-    websim::run_server(args.db, args.config).await
+    init_tracing();
+    websim::run_server(args.db, args.config, mode).await
 }
@@ -0,0 +1,186 @@
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::time::Duration;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use futures_util::{StreamExt, stream};
+
+use crate::backend::{
+    ChatCompletionRequest, ChatCompletionResponse, Choice, ContentStream, LlmBackend, Message,
+    MessageRole, StreamDelta, Usage, log_request,
+};
+use crate::facts;
+
+/// Number of chunks streamed responses are split into
+const STREAM_CHUNKS: usize = 3;
+/// Characters per token when estimating usage
+const CHARS_PER_TOKEN: usize = 4;
+
+/// Offline backend returning deterministic content derived from the requested path and MIME type.
+///
+/// Output lists the reference material headings found in the user prompt so callers can observe
+/// which cached pages were used as context, and ends with a facts block if the prompt asks for one.
+pub struct MockBackend {
+    /// Simulated generation latency, spread across streamed chunks
+    delay: Duration,
+}
+
+impl MockBackend {
+    pub fn new(delay: Duration) -> Self {
+        Self { delay }
+    }
+
+    fn generate(request: &ChatCompletionRequest) -> String {
+        let path = request.path.as_str();
+        let subject = subject(path);
+        let references = references(request);
+
+        let mut hasher = DefaultHasher::new();
+        path.hash(&mut hasher);
+        request.mime_type.hash(&mut hasher);
+        let color = format!("#{:06x}", hasher.finish() & 0xff_ffff);
+
+        let content = match request.mime_type.as_str() {
+            "text/html" => {
+                let subject = escape_xml(&subject);
+                let comments: String = references
+                    .iter()
+                    .map(|r| format!("<!-- reference: {} -->", escape_xml(r)))
+                    .collect();
+                format!(
+                    "<!-- path={path} subject={subject} -->{comments}<!DOCTYPE html><html><head><title>{subject}</title><link rel=\"stylesheet\" href=\"/style.css\"></head><body style=\"color:{color}\"><h1>{subject}</h1><img src=\"{subject}.svg\" alt=\"{subject}\"><ul><li><a href=\"./{subject}/details\">Details</a></li><li><a href=\"../\">Up</a></li><li><a href=\"/\">Home</a></li></ul><script src=\"/app.js\" defer></script></body></html>",
+                    path = escape_xml(path),
+                )
+            }
+            "image/svg+xml" => format!(
+                "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 100 100\"><rect width=\"100\" height=\"100\" fill=\"{color}\"/><text x=\"50\" y=\"50\" text-anchor=\"middle\">{}</text></svg>",
+                escape_xml(&subject)
+            ),
+            "application/json" => serde_json::json!({
+                "path": path,
+                "subject": subject,
+                "color": color,
+                "references": references,
+            })
+            .to_string(),
+            "text/css" => {
+                format!("body{{font-family:sans-serif;color:{color}}}h1{{color:{color}}}")
+            }
+            "application/javascript" => format!(
+                "console.log({});",
+                serde_json::Value::String(format!("mock script for {}", path))
+            ),
+            _ => format!("Mock content for {}", path),
+        };
+
+        if requests_facts(request) {
+            let facts =
+                serde_json::json!({ subject.as_str(): format!("{} are {}", subject, color) });
+            format!("{}\n{}{}</websim-facts>", content, facts::OPEN_TAG, facts)
+        } else {
+            content
+        }
+    }
+}
+
+/// Whether the user prompt asks for the facts the content establishes
+fn requests_facts(request: &ChatCompletionRequest) -> bool {
+    request
+        .messages
+        .iter()
+        .any(|m| m.role == MessageRole::User && m.content.contains(facts::OPEN_TAG))
+}
+
+/// Estimates usage from the length of the prompts and generated content
+fn usage(request: &ChatCompletionRequest, content: &str) -> Usage {
+    let tokens = |chars: usize| chars.div_ceil(CHARS_PER_TOKEN) as u64;
+    let prompt_chars = request
+        .messages
+        .iter()
+        .map(|m| m.content.chars().count())
+        .sum();
+
+    Usage {
+        prompt_tokens: tokens(prompt_chars),
+        completion_tokens: tokens(content.chars().count()),
+        cost: None,
+    }
+}
+
+/// Derives the subject from the last non-empty path segment, without extension or query
+fn subject(path: &str) -> String {
+    let path = path.split('?').next().unwrap_or_default();
+    let segment = path.rsplit('/').find(|s| !s.is_empty()).unwrap_or("home");
+    segment.split('.').next().unwrap_or(segment).to_string()
+}
+
+/// Collects the `### ` reference material headings from the user prompt
+fn references(request: &ChatCompletionRequest) -> Vec<String> {
+    request
+        .messages
+        .iter()
+        .filter(|m| m.role == MessageRole::User)
+        .flat_map(|m| m.content.lines())
+        .filter_map(|line| line.strip_prefix("### "))
+        .map(str::to_string)
+        .collect()
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[async_trait]
+impl LlmBackend for MockBackend {
+    async fn chat_completion(
+        &self,
+        request: ChatCompletionRequest,
+    ) -> Result<ChatCompletionResponse> {
+        log_request("Mock", &request);
+        tokio::time::sleep(self.delay).await;
+
+        let content = Self::generate(&request);
+        Ok(ChatCompletionResponse {
+            id: String::new(),
+            model: request.model.clone(),
+            usage: Some(usage(&request, &content)),
+            choices: vec![Choice {
+                message: Message {
+                    role: MessageRole::Assistant,
+                    content,
+                },
+                finish_reason: Some("stop".to_string()),
+            }],
+        })
+    }
+
+    async fn chat_completion_stream(
+        &self,
+        request: ChatCompletionRequest,
+    ) -> Result<ContentStream> {
+        log_request("Mock", &request);
+
+        let content = Self::generate(&request);
+        let usage = usage(&request, &content);
+
+        let chars: Vec<char> = content.chars().collect();
+        let chunk_size = chars.len().div_ceil(STREAM_CHUNKS).max(1);
+        let chunks: Vec<Result<StreamDelta>> = chars
+            .chunks(chunk_size)
+            .map(|chunk| Ok(StreamDelta::Content(chunk.iter().collect())))
+            .collect();
+
+        let delay = self.delay / STREAM_CHUNKS as u32;
+        let stream = stream::iter(chunks)
+            .then(move |chunk| async move {
+                tokio::time::sleep(delay).await;
+                chunk
+            })
+            .chain(stream::once(async move { Ok(StreamDelta::Usage(usage)) }));
+
+        Ok(Box::pin(stream))
+    }
+}
@@ -0,0 +1,135 @@
+use anyhow::{Result, bail};
+use async_trait::async_trait;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+
+use crate::backend::{
+    ApiError, ChatCompletionRequest, ChatCompletionResponse, Choice, ContentStream, Frame, Framing,
+    LlmBackend, Message, Usage, check_status, content_stream, log_request, read_json,
+};
+
+/// Request body for Ollama's `/api/chat` endpoint
+#[derive(Debug, Serialize)]
+struct OllamaChatRequest<'a> {
+    model: &'a str,
+    messages: &'a [Message],
+    stream: bool,
+}
+
+/// Response body, or a single line of a streamed response, from `/api/chat`
+#[derive(Debug, Deserialize)]
+struct OllamaChatResponse {
+    #[serde(default)]
+    model: String,
+    message: Option<Message>,
+    #[serde(default)]
+    done: bool,
+    done_reason: Option<String>,
+    error: Option<String>,
+    /// Token counts, sent once the generation is done
+    prompt_eval_count: Option<u64>,
+    eval_count: Option<u64>,
+}
+
+impl OllamaChatResponse {
+    fn usage(&self) -> Option<Usage> {
+        self.done.then(|| Usage {
+            prompt_tokens: self.prompt_eval_count.unwrap_or_default(),
+            completion_tokens: self.eval_count.unwrap_or_default(),
+            cost: None,
+        })
+    }
+}
+
+fn parse_line(data: &str) -> Result<Frame> {
+    let line: OllamaChatResponse = serde_json::from_str(data)?;
+    if let Some(error) = line.error {
+        return Err(ApiError::from_status(StatusCode::BAD_GATEWAY, error, None).into());
+    }
+
+    Ok(Frame {
+        usage: line.usage(),
+        content: line.message.map(|m| m.content).unwrap_or_default(),
+        done: line.done,
+    })
+}
+
+/// Client for a local or remote Ollama server's native chat API
+pub struct OllamaClient {
+    client: reqwest::Client,
+    base_url: String,
+}
+
+impl OllamaClient {
+    /// `base_url` is the server root, e.g. `http://localhost:11434`
+    pub fn new(base_url: String) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            base_url,
+        }
+    }
+
+    async fn post(&self, request: &ChatCompletionRequest) -> Result<reqwest::Response> {
+        let url = format!("{}/api/chat", self.base_url.trim_end_matches('/'));
+        let body = OllamaChatRequest {
+            model: &request.model,
+            messages: &request.messages,
+            stream: request.stream,
+        };
+
+        Ok(self
+            .client
+            .post(url)
+            .header("Content-Type", "application/json")
+            .json(&body)
+            .send()
+            .await?)
+    }
+}
+
+#[async_trait]
+impl LlmBackend for OllamaClient {
+    async fn chat_completion(
+        &self,
+        mut request: ChatCompletionRequest,
+    ) -> Result<ChatCompletionResponse> {
+        // Ollama streams unless explicitly told not to
+        request.stream = false;
+        log_request("Ollama", &request);
+
+        let response = self.post(&request).await?;
+        let response = read_json::<OllamaChatResponse>(response).await?;
+
+        if let Some(error) = response.error {
+            return Err(ApiError::from_status(StatusCode::BAD_GATEWAY, error, None).into());
+        }
+
+        let usage = response.usage();
+        let Some(message) = response.message else {
+            bail!("Ollama response is missing a message");
+        };
+
+        Ok(ChatCompletionResponse {
+            id: String::new(),
+            model: response.model,
+            choices: vec![Choice {
+                message,
+                finish_reason: response.done_reason,
+            }],
+            usage,
+        })
+    }
+
+    async fn chat_completion_stream(
+        &self,
+        mut request: ChatCompletionRequest,
+    ) -> Result<ContentStream> {
+        request.stream = true;
+        log_request("Ollama", &request);
+
+        let response = self.post(&request).await?;
+        let response = check_status(response).await?;
+
+        Ok(content_stream(response, Framing::Ndjson, parse_line))
+    }
+}
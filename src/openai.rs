@@ -0,0 +1,153 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use reqwest::StatusCode;
+use secrecy::{ExposeSecret, SecretString};
+use serde::{Deserialize, Serialize};
+
+use crate::backend::{
+    ApiError, ChatCompletionRequest, ChatCompletionResponse, ContentStream, Frame, Framing,
+    LlmBackend, Usage, check_status, content_stream, log_request, read_json,
+};
+
+/// A single server-sent event payload when `stream` is enabled
+#[derive(Debug, Deserialize)]
+struct ChatCompletionChunk {
+    #[serde(default)]
+    choices: Vec<ChunkChoice>,
+    error: Option<serde_json::Value>,
+    /// Sent in a final chunk without choices when usage is requested
+    usage: Option<Usage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChunkChoice {
+    delta: Delta,
+}
+
+#[derive(Debug, Deserialize)]
+struct Delta {
+    content: Option<String>,
+}
+
+/// Parses an OpenAI chat completion stream event
+pub(crate) fn parse_chunk(data: &str) -> Result<Frame> {
+    if data == "[DONE]" {
+        return Ok(Frame {
+            done: true,
+            ..Default::default()
+        });
+    }
+
+    let chunk: ChatCompletionChunk = serde_json::from_str(data)?;
+    if chunk.error.is_some() {
+        // Errors after the response has started carry their status code in the body, if at all
+        return Err(ApiError::from_body(StatusCode::BAD_GATEWAY, data, None).into());
+    }
+
+    Ok(Frame {
+        content: chunk
+            .choices
+            .into_iter()
+            .filter_map(|choice| choice.delta.content)
+            .collect(),
+        usage: chunk.usage,
+        done: false,
+    })
+}
+
+/// Streaming request asking for usage to be reported in the final chunk
+#[derive(Debug, Serialize)]
+struct StreamRequest<'a> {
+    #[serde(flatten)]
+    request: &'a ChatCompletionRequest,
+    stream_options: StreamOptions,
+}
+
+#[derive(Debug, Serialize)]
+struct StreamOptions {
+    include_usage: bool,
+}
+
+/// Client for any server implementing the OpenAI chat completions API,
+/// such as llama.cpp, vLLM or an internal gateway
+pub struct OpenAiCompatibleClient {
+    client: reqwest::Client,
+    base_url: String,
+    auth_header: String,
+    auth_scheme: String,
+    api_key: Option<SecretString>,
+}
+
+impl OpenAiCompatibleClient {
+    /// `base_url` is the API root that `/chat/completions` is appended to, e.g. `http://localhost:8080/v1`.
+    /// The API key is sent as `<auth_header>: <auth_scheme> <key>`, or just the key if the scheme is empty.
+    pub fn new(
+        base_url: String,
+        auth_header: String,
+        auth_scheme: String,
+        api_key: Option<SecretString>,
+    ) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            base_url,
+            auth_header,
+            auth_scheme,
+            api_key,
+        }
+    }
+
+    /// Posts a JSON body to the chat completions endpoint
+    pub(crate) async fn post(&self, body: &impl Serialize) -> Result<reqwest::Response> {
+        let url = format!("{}/chat/completions", self.base_url.trim_end_matches('/'));
+        let mut builder = self
+            .client
+            .post(url)
+            .header("Content-Type", "application/json");
+
+        if let Some(ref api_key) = self.api_key {
+            let value = if self.auth_scheme.is_empty() {
+                api_key.expose_secret().to_string()
+            } else {
+                format!("{} {}", self.auth_scheme, api_key.expose_secret())
+            };
+            builder = builder.header(self.auth_header.as_str(), value);
+        }
+
+        Ok(builder.json(body).send().await?)
+    }
+}
+
+#[async_trait]
+impl LlmBackend for OpenAiCompatibleClient {
+    async fn chat_completion(
+        &self,
+        request: ChatCompletionRequest,
+    ) -> Result<ChatCompletionResponse> {
+        log_request("OpenAI-compatible", &request);
+
+        let response = self.post(&request).await?;
+        let response = read_json::<ChatCompletionResponse>(response).await?;
+
+        Ok(response)
+    }
+
+    async fn chat_completion_stream(
+        &self,
+        mut request: ChatCompletionRequest,
+    ) -> Result<ContentStream> {
+        request.stream = true;
+        log_request("OpenAI-compatible", &request);
+
+        let response = self
+            .post(&StreamRequest {
+                request: &request,
+                stream_options: StreamOptions {
+                    include_usage: true,
+                },
+            })
+            .await?;
+        let response = check_status(response).await?;
+
+        Ok(content_stream(response, Framing::Sse, parse_chunk))
+    }
+}
@@ -1,20 +1,17 @@
 use anyhow::Result;
-use secrecy::{ExposeSecret, SecretString};
+use async_trait::async_trait;
+use secrecy::SecretString;
 use serde::{Deserialize, Serialize};
 use strum::{Display, EnumString};
 use tracing::debug;
 
-const OPENROUTER_API_URL: &str = "https://openrouter.ai/api/v1/chat/completions";
+use crate::backend::{
+    ChatCompletionRequest, ChatCompletionResponse, ContentStream, Framing, LlmBackend,
+    check_status, content_stream, log_request, read_json,
+};
+use crate::openai::{OpenAiCompatibleClient, parse_chunk};
 
-/// Message role in the chat conversation
-#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Display, EnumString)]
-#[strum(serialize_all = "lowercase")]
-#[serde(rename_all = "lowercase")]
-pub enum MessageRole {
-    System,
-    User,
-    Assistant,
-}
+const OPENROUTER_API_URL: &str = "https://openrouter.ai/api/v1";
 
 /// Provider sorting preference for OpenRouter
 #[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Display, EnumString)]
@@ -26,85 +23,81 @@ pub enum ProviderSort {
     Latency,
 }
 
-#[derive(Debug, Serialize)]
-pub struct ChatCompletionRequest {
-    pub model: String,
-    pub messages: Vec<Message>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub provider: Option<ProviderPrefs>,
-}
-
 #[derive(Debug, Serialize)]
 pub struct ProviderPrefs {
     pub sort: ProviderSort,
     // (optionally expose more fields later: order, only, ignore, allow_fallbacks, etc.)
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct Message {
-    pub role: MessageRole,
-    pub content: String,
-}
-
-#[derive(Debug, Deserialize)]
-pub struct ChatCompletionResponse {
-    #[allow(dead_code)]
-    pub id: String,
-    #[allow(dead_code)]
-    pub model: String,
-    pub choices: Vec<Choice>,
+/// Chat completion request with OpenRouter-specific routing preferences
+#[derive(Debug, Serialize)]
+struct OpenRouterRequest<'a> {
+    #[serde(flatten)]
+    request: &'a ChatCompletionRequest,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    provider: Option<&'a ProviderPrefs>,
+    usage: UsagePrefs,
 }
 
-#[derive(Debug, Deserialize)]
-pub struct Choice {
-    pub message: Message,
-    #[allow(dead_code)]
-    pub finish_reason: Option<String>,
+/// Asks OpenRouter to include token counts and cost in the response
+#[derive(Debug, Serialize)]
+struct UsagePrefs {
+    include: bool,
 }
 
 pub struct OpenRouterClient {
-    client: reqwest::Client,
-    api_key: SecretString,
+    inner: OpenAiCompatibleClient,
+    provider: Option<ProviderPrefs>,
 }
 
 impl OpenRouterClient {
-    pub fn new(api_key: SecretString) -> Self {
+    pub fn new(api_key: SecretString, provider_sort: Option<ProviderSort>) -> Self {
         Self {
-            client: reqwest::Client::new(),
-            api_key,
+            inner: OpenAiCompatibleClient::new(
+                OPENROUTER_API_URL.to_string(),
+                "Authorization".to_string(),
+                "Bearer".to_string(),
+                Some(api_key),
+            ),
+            provider: provider_sort.map(|sort| ProviderPrefs { sort }),
         }
     }
 
-    pub async fn chat_completion(
-        &self,
-        request: ChatCompletionRequest,
-    ) -> Result<ChatCompletionResponse> {
-        // Log the request in a more readable format
-        debug!("OpenRouter API request:");
-        debug!("  Model: {}", request.model);
-        if let Some(ref provider) = request.provider {
+    fn wrap<'a>(&'a self, request: &'a ChatCompletionRequest) -> OpenRouterRequest<'a> {
+        log_request("OpenRouter", request);
+        if let Some(ref provider) = self.provider {
             debug!("  Provider sort: {}", provider.sort);
         }
-        debug!("  Messages:");
-        for (i, msg) in request.messages.iter().enumerate() {
-            debug!("    [{}] Role: {}", i, msg.role);
-            debug!("    [{}] Content:\n{}", i, msg.content);
+
+        OpenRouterRequest {
+            request,
+            provider: self.provider.as_ref(),
+            usage: UsagePrefs { include: true },
         }
+    }
+}
 
-        let response = self
-            .client
-            .post(OPENROUTER_API_URL)
-            .header(
-                "Authorization",
-                format!("Bearer {}", self.api_key.expose_secret()),
-            )
-            .header("Content-Type", "application/json")
-            .json(&request)
-            .send()
-            .await?
-            .json::<ChatCompletionResponse>()
-            .await?;
+#[async_trait]
+impl LlmBackend for OpenRouterClient {
+    async fn chat_completion(
+        &self,
+        request: ChatCompletionRequest,
+    ) -> Result<ChatCompletionResponse> {
+        let response = self.inner.post(&self.wrap(&request)).await?;
+        let response = read_json::<ChatCompletionResponse>(response).await?;
 
         Ok(response)
     }
+
+    async fn chat_completion_stream(
+        &self,
+        mut request: ChatCompletionRequest,
+    ) -> Result<ContentStream> {
+        request.stream = true;
+
+        let response = self.inner.post(&self.wrap(&request)).await?;
+        let response = check_status(response).await?;
+
+        Ok(content_stream(response, Framing::Sse, parse_chunk))
+    }
 }
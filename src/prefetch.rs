@@ -0,0 +1,122 @@
+use std::sync::{Arc, Weak};
+
+use anyhow::Result;
+use axum::body::Body;
+use axum::extract::{Request, State};
+use axum::http::header;
+use axum::response::IntoResponse;
+use tokio::sync::{Semaphore, mpsc};
+use tracing::{info, warn};
+
+use crate::handler::handle;
+use crate::links::{self, LinkKind};
+use crate::state::AppState;
+
+/// Request extension marking a request made by the prefetcher, whose generation counts as speculative
+#[derive(Debug, Clone, Copy)]
+pub struct Speculative;
+
+/// A linked page waiting to be generated in the background
+#[derive(Debug)]
+pub struct PrefetchJob {
+    pub path_and_query: String,
+    /// Page the link was found on, sent as the referer so it is used as reference material
+    pub referer: String,
+}
+
+/// Bounded queue of pages to generate in the background
+pub struct Prefetcher {
+    queue: mpsc::Sender<PrefetchJob>,
+}
+
+impl Prefetcher {
+    /// Creates a queue holding up to `queue_size` pages, returning the receiving end for [`run`]
+    pub fn new(queue_size: usize) -> (Self, mpsc::Receiver<PrefetchJob>) {
+        let (queue, receiver) = mpsc::channel(queue_size.max(1));
+        (Self { queue }, receiver)
+    }
+
+    /// Queues up to `limit` pages linked from newly generated HTML at `page`.
+    /// Links are dropped rather than waited on once the queue is full.
+    pub fn enqueue_links(&self, page: &str, content: &str, limit: usize) {
+        if limit == 0 {
+            return;
+        }
+        let links = links::extract(content, page)
+            .into_iter()
+            .filter(|link| link.kind == LinkKind::Navigation)
+            .take(limit);
+
+        for link in links {
+            let job = PrefetchJob {
+                path_and_query: link.path_and_query,
+                referer: page.to_string(),
+            };
+            match self.queue.try_send(job) {
+                Ok(()) => {}
+                Err(mpsc::error::TrySendError::Full(job)) => {
+                    info!(link = %job.path_and_query, "Prefetch queue full, dropping link");
+                    break;
+                }
+                Err(mpsc::error::TrySendError::Closed(_)) => break,
+            }
+        }
+    }
+}
+
+/// Generates queued pages with at most `concurrency` at once, until the application is dropped
+pub async fn run(
+    state: Weak<AppState>,
+    mut queue: mpsc::Receiver<PrefetchJob>,
+    concurrency: usize,
+) {
+    let permits = Arc::new(Semaphore::new(concurrency.max(1)));
+
+    while let Some(job) = queue.recv().await {
+        let Ok(permit) = Arc::clone(&permits).acquire_owned().await else {
+            break;
+        };
+        let Some(state) = state.upgrade() else {
+            break;
+        };
+
+        let task = async move {
+            if let Err(e) = prefetch(state, &job).await {
+                warn!(link = %job.path_and_query, error = %e, "Prefetch failed");
+            }
+            drop(permit);
+        };
+        if let Err(e) = tokio::task::Builder::new().name("prefetch").spawn(task) {
+            warn!(error = %e, "Failed to spawn prefetch task");
+        }
+    }
+}
+
+/// Requests a page through the same pipeline as the server, so it is served from the cache if
+/// already stored and generated otherwise, unless the speculative spending cap has been reached
+async fn prefetch(state: Arc<AppState>, job: &PrefetchJob) -> Result<()> {
+    let cap = &state.config.prefetch;
+    let spending = state.db.speculative_spending().await?;
+    if cap.daily.is_reached(&spending.today) || cap.monthly.is_reached(&spending.this_month) {
+        info!(link = %job.path_and_query, "Prefetch spending cap reached, skipping link");
+        return Ok(());
+    }
+
+    let request = Request::get(&job.path_and_query)
+        .header(header::REFERER, &job.referer)
+        .extension(Speculative)
+        .body(Body::empty())?;
+    let response = handle(State(state), request).await.into_response();
+
+    // Streamed content is only stored once its body has been read to the end
+    let status = response.status();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await?;
+    info!(
+        status = status.as_u16(),
+        bytes = body.len(),
+        "Prefetched {}",
+        job.path_and_query
+    );
+
+    Ok(())
+}
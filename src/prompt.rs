@@ -0,0 +1,248 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Context, Result};
+use axum::http::HeaderMap;
+use minijinja::Environment;
+use serde::Serialize;
+
+use crate::config::{WebSimConfig, WorldConfig};
+use crate::facts::{self, Fact};
+
+/// Template user prompts are rendered from unless the config sets another
+pub const DEFAULT_USER_PROMPT_TEMPLATE: &str = r#"Generate content for path: {{ path_and_query }}
+{% if world %}
+## World
+
+The site is part of the following world, and everything generated must stay consistent with it.
+
+{{ world_description }}
+{% if facts %}
+Facts established by earlier pages:
+{% for fact in facts %}- {{ fact.name }}: {{ fact.statement }}
+{% endfor %}{% endif %}{% if facts_instructions %}
+{{ facts_instructions }}
+{% endif %}{% endif %}
+The following materials are context-only. They are **not part of the output**.
+Use them only to stay consistent with style or data conventions.
+
+Headers: {{ referer or "none" }}
+Reference materials: {{ reference_materials or "none" }}"#;
+
+/// Name of the template used for content types without their own
+const DEFAULT_TEMPLATE: &str = "default";
+
+/// Values user prompt templates are rendered with
+#[derive(Debug, Serialize)]
+pub struct PromptContext<'a> {
+    /// Normalized request path, e.g. `/fruits`
+    pub path: &'a str,
+    /// Query string without the `?`, empty if there is none
+    pub query: &'a str,
+    /// Path and query as requested, e.g. `/fruits?color=red`
+    pub path_and_query: &'a str,
+    pub method: &'a str,
+    /// MIME type of the content being generated
+    pub mime_type: &'a str,
+    /// Request headers keyed by lowercase name, repeated headers joined with `, `
+    pub headers: BTreeMap<&'a str, String>,
+    /// `Referer` header, empty if not sent
+    pub referer: &'a str,
+    /// Stored pages related to the request, and the body of POST requests, empty if there are none
+    pub reference_materials: &'a str,
+    /// Request body, empty if there is none
+    pub body: &'a str,
+    /// The site's world, if configured
+    pub world: Option<&'a WorldConfig>,
+    /// The site's world described for the model
+    pub world_description: Option<String>,
+    /// Facts earlier pages established, oldest first
+    pub facts: Vec<Fact>,
+    /// Asks the model to record the facts the page establishes, if the site keeps them
+    pub facts_instructions: Option<&'static str>,
+    /// Time of the request in RFC 3339 format, in UTC
+    pub timestamp: String,
+}
+
+impl<'a> PromptContext<'a> {
+    /// Context for a request, without a world or facts
+    pub fn new(
+        path_and_query: &'a str,
+        path: &'a str,
+        method: &'a str,
+        mime_type: &'a str,
+    ) -> Self {
+        Self {
+            path,
+            query: path_and_query
+                .split_once('?')
+                .map_or("", |(_, query)| query),
+            path_and_query,
+            method,
+            mime_type,
+            headers: BTreeMap::new(),
+            referer: "",
+            reference_materials: "",
+            body: "",
+            world: None,
+            world_description: None,
+            facts: Vec::new(),
+            facts_instructions: None,
+            timestamp: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+        }
+    }
+
+    pub fn headers(mut self, headers: &'a HeaderMap) -> Self {
+        for (name, value) in headers {
+            let value = String::from_utf8_lossy(value.as_bytes());
+            self.headers
+                .entry(name.as_str())
+                .and_modify(|joined| {
+                    joined.push_str(", ");
+                    joined.push_str(&value);
+                })
+                .or_insert_with(|| value.into_owned());
+        }
+        self
+    }
+
+    /// Adds the site's world along with the facts earlier pages established, asking the model
+    /// to record new ones if the site keeps them
+    pub fn world(mut self, world: &'a WorldConfig, facts: Vec<Fact>) -> Self {
+        self.world = Some(world);
+        self.world_description = Some(world.describe());
+        self.facts = facts;
+        self.facts_instructions = world.facts.then_some(facts::INSTRUCTIONS);
+        self
+    }
+}
+
+/// User prompt templates, compiled once at startup
+pub struct PromptTemplates {
+    /// The default template, and the templates of content types with their own keyed by MIME type
+    env: Environment<'static>,
+}
+
+impl PromptTemplates {
+    /// Compiles the configured templates, failing on unreadable files and syntax errors
+    pub fn compile(config: &WebSimConfig) -> Result<Self> {
+        let mut env = Environment::new();
+
+        let default = match &config.user_prompt_template {
+            Some(template) => template.source()?,
+            None => DEFAULT_USER_PROMPT_TEMPLATE.to_string(),
+        };
+        env.add_template_owned(DEFAULT_TEMPLATE, default)
+            .context("Invalid user_prompt_template")?;
+
+        for (mime_type, content_type) in &config.content_types {
+            if let Some(template) = &content_type.user_prompt_template {
+                env.add_template_owned(mime_type.clone(), template.source()?)
+                    .with_context(|| format!("Invalid user_prompt_template for {}", mime_type))?;
+            }
+        }
+
+        Ok(Self { env })
+    }
+
+    /// Renders the user prompt for a content type, with its own template if it has one
+    pub fn render(&self, mime_type: &str, context: &PromptContext<'_>) -> Result<String> {
+        let template = self
+            .env
+            .get_template(mime_type)
+            .or_else(|_| self.env.get_template(DEFAULT_TEMPLATE))?;
+        Ok(template.render(context)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PromptTemplate;
+
+    const CONFIG: &str = r#"
+content_types:
+  text/html:
+    model: model
+    system_prompt: html
+    content_type_header: text/html
+    extensions: [html]
+  application/json:
+    model: model
+    system_prompt: json
+    content_type_header: application/json
+    extensions: [json]
+    user_prompt_template: "{{ method }} {{ path }} ? {{ query }} as {{ mime_type }}: {{ headers['x-fruit'] }} {{ body }}"
+"#;
+
+    fn config() -> WebSimConfig {
+        ::config::Config::builder()
+            .add_source(::config::File::from_str(CONFIG, ::config::FileFormat::Yaml))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_default_template() {
+        let templates = PromptTemplates::compile(&config()).unwrap();
+
+        // Prompts without a world are unchanged, so recorded fixtures still match
+        let context = PromptContext::new("/fruits", "/fruits", "GET", "text/html");
+        assert_eq!(
+            templates.render("text/html", &context).unwrap(),
+            "Generate content for path: /fruits\n\nThe following materials are context-only. They are **not part of the output**.\nUse them only to stay consistent with style or data conventions.\n\nHeaders: none\nReference materials: none"
+        );
+
+        let world = WorldConfig {
+            name: Some("The Daily Planet".to_string()),
+            description: Some("A tabloid in a world without the moon landing.\n".to_string()),
+            era: Some("1969".to_string()),
+            entities: indexmap::IndexMap::from([("Perry White".to_string(), "Editor".to_string())]),
+            facts: true,
+            ..WorldConfig::default()
+        };
+        let facts = vec![Fact {
+            name: "Price".to_string(),
+            statement: "The paper costs 10 cents".to_string(),
+        }];
+        let context =
+            PromptContext::new("/fruits", "/fruits", "GET", "text/html").world(&world, facts);
+        let prompt = templates.render("text/html", &context).unwrap();
+        let expected = format!(
+            "Generate content for path: /fruits\n\n## World\n\nThe site is part of the following world, and everything generated must stay consistent with it.\n\nSite: The Daily Planet\nEra: 1969\n\nA tabloid in a world without the moon landing.\n\nKey entities:\n- Perry White: Editor\n\nFacts established by earlier pages:\n- Price: The paper costs 10 cents\n\n{}\n\nThe following materials",
+            facts::INSTRUCTIONS
+        );
+        assert!(prompt.starts_with(&expected), "{}", prompt);
+    }
+
+    #[test]
+    fn test_content_type_template() {
+        let templates = PromptTemplates::compile(&config()).unwrap();
+        let mut headers = HeaderMap::new();
+        headers.append("X-Fruit", "apple".parse().unwrap());
+        headers.append("X-Fruit", "pear".parse().unwrap());
+
+        let mut context =
+            PromptContext::new("/fruits?color=red", "/fruits", "POST", "application/json")
+                .headers(&headers);
+        context.body = "{}";
+        assert_eq!(
+            templates.render("application/json", &context).unwrap(),
+            "POST /fruits ? color=red as application/json: apple, pear {}"
+        );
+    }
+
+    #[test]
+    fn test_invalid_template() {
+        let mut config = config();
+        config.user_prompt_template = Some(PromptTemplate::Inline("{% if path %}".to_string()));
+        let error = PromptTemplates::compile(&config).err().unwrap();
+        assert_eq!(error.to_string(), "Invalid user_prompt_template");
+
+        config.user_prompt_template = Some(PromptTemplate::File {
+            file: "missing.j2".into(),
+        });
+        assert!(PromptTemplates::compile(&config).is_err());
+    }
+}
@@ -0,0 +1,296 @@
+use std::future::Future;
+use std::time::Duration;
+
+use anyhow::{Result, anyhow};
+use futures_util::{StreamExt, stream};
+use tracing::{info, warn};
+
+use crate::backend::{
+    ApiError, ChatCompletionRequest, ChatCompletionResponse, ContentStream, LlmBackend,
+};
+use crate::config::ContentTypeConfig;
+
+/// Delay before the first retry, doubled for each further attempt
+const BASE_RETRY_DELAY: Duration = Duration::from_millis(500);
+/// Upper bound on any single retry delay, including ones requested by `Retry-After`
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Timeout, retry and fallback-model policy for generating a content type
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Models to try in order, starting with the primary model
+    pub models: Vec<String>,
+    /// Limit on each attempt, which for streams ends once the first delta arrives
+    pub timeout: Option<Duration>,
+    pub max_retries: u32,
+    pub base_delay: Duration,
+}
+
+impl RetryPolicy {
+    pub fn from_config(config: &ContentTypeConfig) -> Self {
+        Self {
+            models: std::iter::once(&config.model)
+                .chain(&config.fallback_models)
+                .cloned()
+                .collect(),
+            timeout: config.timeout_secs.map(Duration::from_secs),
+            max_retries: config.max_retries,
+            base_delay: BASE_RETRY_DELAY,
+        }
+    }
+
+    /// Delay before retrying after `attempt` (zero-based) failed with `error`
+    fn delay(&self, attempt: u32, error: &anyhow::Error) -> Duration {
+        let retry_after = error
+            .downcast_ref::<ApiError>()
+            .and_then(ApiError::retry_after);
+        retry_after
+            .unwrap_or_else(|| self.base_delay.saturating_mul(2u32.saturating_pow(attempt)))
+            .min(MAX_RETRY_DELAY)
+    }
+}
+
+/// Whether a failed attempt may succeed if retried with the same model
+fn is_retryable(error: &anyhow::Error) -> bool {
+    if let Some(e) = error.downcast_ref::<ApiError>() {
+        return e.is_transient();
+    }
+    if let Some(e) = error.downcast_ref::<reqwest::Error>() {
+        return !e.is_decode() && !e.is_builder();
+    }
+    false
+}
+
+/// Runs `call` for each model in turn, retrying transient failures with exponential backoff.
+/// Returns the model that succeeded alongside its result, or the last error if every model failed.
+async fn with_retries<T, F, Fut>(
+    policy: &RetryPolicy,
+    request: ChatCompletionRequest,
+    call: F,
+) -> Result<(String, T)>
+where
+    F: Fn(ChatCompletionRequest) -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut last_error = None;
+
+    for model in &policy.models {
+        for attempt in 0..=policy.max_retries {
+            let mut request = request.clone();
+            request.model = model.clone();
+
+            let result = match policy.timeout {
+                Some(timeout) => tokio::time::timeout(timeout, call(request))
+                    .await
+                    .unwrap_or_else(|_| {
+                        Err(ApiError::Timeout(format!(
+                            "No response after {}s",
+                            timeout.as_secs_f64()
+                        ))
+                        .into())
+                    }),
+                None => call(request).await,
+            };
+
+            let error = match result {
+                Ok(value) => {
+                    info!(model = %model, attempt = attempt + 1, "API attempt succeeded");
+                    return Ok((model.clone(), value));
+                }
+                Err(error) => error,
+            };
+
+            let retryable = is_retryable(&error);
+            warn!(
+                model = %model,
+                attempt = attempt + 1,
+                retryable = retryable,
+                error = %error,
+                "API attempt failed"
+            );
+
+            if retryable && attempt < policy.max_retries {
+                let delay = policy.delay(attempt, &error);
+                info!(delay_secs = %format!("{:.2}", delay.as_secs_f64()), "Retrying");
+                tokio::time::sleep(delay).await;
+            } else {
+                last_error = Some(error);
+                break;
+            }
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| anyhow!("No models configured")))
+}
+
+/// Sends a chat completion request according to the retry policy
+pub async fn chat_completion(
+    backend: &dyn LlmBackend,
+    policy: &RetryPolicy,
+    request: ChatCompletionRequest,
+) -> Result<(String, ChatCompletionResponse)> {
+    with_retries(policy, request, |request| backend.chat_completion(request)).await
+}
+
+/// Sends a streaming chat completion request according to the retry policy.
+/// An attempt only succeeds once the first delta arrives, so failures before any content
+/// has been forwarded can still be retried or fall back to another model.
+pub async fn chat_completion_stream(
+    backend: &dyn LlmBackend,
+    policy: &RetryPolicy,
+    request: ChatCompletionRequest,
+) -> Result<(String, ContentStream)> {
+    with_retries(policy, request, |request| async move {
+        let mut stream = backend.chat_completion_stream(request).await?;
+        let stream: ContentStream = match stream.next().await {
+            Some(Ok(first)) => Box::pin(stream::once(async move { Ok(first) }).chain(stream)),
+            Some(Err(e)) => return Err(e),
+            None => Box::pin(stream::empty()),
+        };
+        Ok(stream)
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    use async_trait::async_trait;
+    use reqwest::StatusCode;
+
+    use super::*;
+    use crate::backend::{Choice, Message, MessageRole, StreamDelta};
+
+    /// Fails a set number of times per model before succeeding
+    struct FlakyBackend {
+        failures: Mutex<HashMap<String, (u32, StatusCode)>>,
+        calls: Mutex<Vec<String>>,
+    }
+
+    impl FlakyBackend {
+        fn new(failures: &[(&str, u32, StatusCode)]) -> Self {
+            Self {
+                failures: Mutex::new(
+                    failures
+                        .iter()
+                        .map(|(model, n, status)| (model.to_string(), (*n, *status)))
+                        .collect(),
+                ),
+                calls: Mutex::new(Vec::new()),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl LlmBackend for FlakyBackend {
+        async fn chat_completion(
+            &self,
+            request: ChatCompletionRequest,
+        ) -> Result<ChatCompletionResponse> {
+            self.calls.lock().unwrap().push(request.model.clone());
+
+            if let Some((remaining, status)) = self.failures.lock().unwrap().get_mut(&request.model)
+                && *remaining > 0
+            {
+                *remaining -= 1;
+                return Err(ApiError::from_status(*status, String::new(), None).into());
+            }
+
+            Ok(ChatCompletionResponse {
+                id: String::new(),
+                model: request.model.clone(),
+                choices: vec![Choice {
+                    message: Message {
+                        role: MessageRole::Assistant,
+                        content: request.model,
+                    },
+                    finish_reason: None,
+                }],
+                usage: None,
+            })
+        }
+
+        async fn chat_completion_stream(
+            &self,
+            request: ChatCompletionRequest,
+        ) -> Result<ContentStream> {
+            let response = self.chat_completion(request).await?;
+            let content = response.choices[0].message.content.clone();
+            Ok(Box::pin(stream::once(async move {
+                Ok(StreamDelta::Content(content))
+            })))
+        }
+    }
+
+    fn policy(models: &[&str], max_retries: u32) -> RetryPolicy {
+        RetryPolicy {
+            models: models.iter().map(|m| m.to_string()).collect(),
+            timeout: None,
+            max_retries,
+            base_delay: Duration::from_millis(1),
+        }
+    }
+
+    fn request() -> ChatCompletionRequest {
+        ChatCompletionRequest {
+            model: String::new(),
+            messages: Vec::new(),
+            stream: false,
+            path: "/".to_string(),
+            mime_type: "text/html".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retries_transient_failures() {
+        let backend = FlakyBackend::new(&[("a", 2, StatusCode::TOO_MANY_REQUESTS)]);
+
+        let (model, _) = chat_completion(&backend, &policy(&["a", "b"], 2), request())
+            .await
+            .unwrap();
+
+        assert_eq!(model, "a");
+        assert_eq!(*backend.calls.lock().unwrap(), ["a", "a", "a"]);
+    }
+
+    #[tokio::test]
+    async fn test_falls_back_to_next_model() {
+        // Client errors are not retried with the same model
+        let backend = FlakyBackend::new(&[
+            ("a", 1, StatusCode::BAD_REQUEST),
+            ("b", 5, StatusCode::SERVICE_UNAVAILABLE),
+        ]);
+
+        let (model, mut stream) =
+            chat_completion_stream(&backend, &policy(&["a", "b", "c"], 1), request())
+                .await
+                .unwrap();
+
+        assert_eq!(model, "c");
+        assert_eq!(
+            stream.next().await.unwrap().unwrap(),
+            StreamDelta::Content("c".to_string())
+        );
+        assert_eq!(*backend.calls.lock().unwrap(), ["a", "b", "b", "c"]);
+    }
+
+    #[tokio::test]
+    async fn test_returns_last_error_when_all_models_fail() {
+        let backend = FlakyBackend::new(&[("a", 5, StatusCode::BAD_GATEWAY)]);
+
+        let error = chat_completion(&backend, &policy(&["a"], 1), request())
+            .await
+            .unwrap_err();
+
+        assert!(matches!(
+            error.downcast_ref::<ApiError>().unwrap(),
+            ApiError::Upstream {
+                status: StatusCode::BAD_GATEWAY,
+                ..
+            }
+        ));
+        assert_eq!(backend.calls.lock().unwrap().len(), 2);
+    }
+}
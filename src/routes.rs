@@ -0,0 +1,213 @@
+use anyhow::{Context, Result, bail};
+use indexmap::IndexMap;
+use regex::Regex;
+
+use crate::config::{ContentTypeConfig, RouteConfig, WebSimConfig};
+
+/// A configured route, with its pattern compiled and its overrides applied to each content type
+#[derive(Debug)]
+pub struct Route {
+    /// Regex matched against request paths, translated from the glob if the route was given one
+    pub pattern: Regex,
+    /// MIME type served regardless of the `Accept` header and extension, if the route sets one
+    pub mime_type: Option<String>,
+    /// The configured content types with the route's overrides applied, keyed by MIME type
+    pub content_types: IndexMap<String, ContentTypeConfig>,
+}
+
+/// Routes in config order, compiled once at startup
+#[derive(Debug)]
+pub struct Routes(Vec<Route>);
+
+impl Routes {
+    /// Compiles the configured routes, failing on invalid patterns or unknown content types and backends
+    pub fn compile(config: &WebSimConfig) -> Result<Self> {
+        config
+            .routes
+            .iter()
+            .enumerate()
+            .map(|(index, route)| {
+                Route::compile(route, config)
+                    .with_context(|| format!("Invalid route {}", index + 1))
+            })
+            .collect::<Result<_>>()
+            .map(Self)
+    }
+
+    /// The first route whose pattern matches `path`
+    pub fn find(&self, path: &str) -> Option<&Route> {
+        self.0.iter().find(|route| route.pattern.is_match(path))
+    }
+
+    /// A content type as configured for `path`, with the overrides of the route matching it applied
+    pub fn content_type<'a>(
+        &'a self,
+        config: &'a WebSimConfig,
+        path: &str,
+        mime_type: &str,
+    ) -> Option<&'a ContentTypeConfig> {
+        match self.find(path) {
+            Some(route) => route
+                .content_type(mime_type)
+                .map(|(_, content_type)| content_type),
+            None => config.content_types.get(mime_type),
+        }
+    }
+}
+
+impl Route {
+    fn compile(route: &RouteConfig, config: &WebSimConfig) -> Result<Self> {
+        let pattern = match (&route.path, &route.regex) {
+            (Some(glob), None) => glob_to_regex(glob),
+            (None, Some(regex)) => regex.clone(),
+            _ => bail!("Routes need exactly one of `path` or `regex`"),
+        };
+        let pattern =
+            Regex::new(&pattern).with_context(|| format!("Invalid pattern: {}", pattern))?;
+
+        if let Some(mime_type) = &route.content_type
+            && !config.content_types.contains_key(mime_type)
+        {
+            bail!("Route refers to unknown content type: {}", mime_type);
+        }
+        if let Some(backend) = &route.backend
+            && !config.backends.contains_key(backend)
+        {
+            bail!("Route refers to unknown backend: {}", backend);
+        }
+
+        let content_types = config
+            .content_types
+            .iter()
+            .map(|(mime_type, content_type)| (mime_type.clone(), route.apply(content_type)))
+            .collect();
+
+        Ok(Self {
+            pattern,
+            mime_type: route.content_type.clone(),
+            content_types,
+        })
+    }
+
+    /// A content type with this route's overrides applied
+    pub fn content_type(&self, mime_type: &str) -> Option<(&String, &ContentTypeConfig)> {
+        self.content_types.get_key_value(mime_type)
+    }
+}
+
+impl RouteConfig {
+    /// Copy of `content_type` with this route's overrides applied
+    fn apply(&self, content_type: &ContentTypeConfig) -> ContentTypeConfig {
+        let mut content_type = content_type.clone();
+        if let Some(backend) = &self.backend {
+            content_type.backend = backend.clone();
+        }
+        if let Some(model) = &self.model {
+            content_type.model = model.clone();
+        }
+        if let Some(system_prompt) = &self.system_prompt {
+            content_type.system_prompt = system_prompt.clone();
+        }
+        if let Some(ttl_secs) = self.ttl_secs {
+            content_type.ttl_secs = Some(ttl_secs);
+        }
+        if let Some(cache_control) = &self.cache_control {
+            content_type.cache_control = Some(cache_control.clone());
+        }
+        if let Some(reference_materials) = self.reference_materials {
+            content_type.reference_materials = reference_materials;
+        }
+        content_type
+    }
+}
+
+/// Translates a path glob into an anchored regex. `*` matches within a segment, `**` across segments,
+/// and a trailing `/**` also matches the path it follows, so `/api/**` matches `/api` itself.
+fn glob_to_regex(glob: &str) -> String {
+    let (glob, any_below) = match glob.strip_suffix("/**") {
+        Some(prefix) => (prefix, true),
+        None => (glob, false),
+    };
+
+    let mut regex = String::from("^");
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                regex.push_str(".*");
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    if any_below {
+        regex.push_str("(?:/.*)?");
+    }
+    regex.push('$');
+    regex
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_route_overrides_content_type_ttl() {
+        let config: WebSimConfig = ::config::Config::builder()
+            .add_source(::config::File::from_str(
+                r#"
+content_types:
+  text/html:
+    model: model
+    system_prompt: html
+    content_type_header: text/html
+    extensions: [html]
+  application/json:
+    model: model
+    system_prompt: json
+    content_type_header: application/json
+    extensions: [json]
+    ttl_secs: 300
+routes:
+  - path: /api/**
+    ttl_secs: 3600
+"#,
+                ::config::FileFormat::Yaml,
+            ))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+        let routes = Routes::compile(&config).unwrap();
+        let ttl = |path, mime_type| {
+            routes
+                .content_type(&config, path, mime_type)
+                .and_then(ContentTypeConfig::ttl)
+                .map(|ttl| ttl.as_secs())
+        };
+
+        assert_eq!(ttl("/api/fruits.json", "application/json"), Some(3600));
+        assert_eq!(ttl("/api", "text/html"), Some(3600));
+        assert_eq!(ttl("/fruits.json", "application/json"), Some(300));
+        assert_eq!(ttl("/fruits", "text/html"), None);
+        assert_eq!(ttl("/fruits", "text/css"), None);
+    }
+
+    #[test]
+    fn test_glob_to_regex() {
+        let matches =
+            |glob: &str, path: &str| Regex::new(&glob_to_regex(glob)).unwrap().is_match(path);
+
+        assert!(matches("/api/**", "/api"));
+        assert!(matches("/api/**", "/api/fruits/apples"));
+        assert!(!matches("/api/**", "/apis"));
+        assert!(matches("/blog/*/comments", "/blog/first-post/comments"));
+        assert!(!matches("/blog/*/comments", "/blog/2024/01/comments"));
+        assert!(!matches("/blog/*/comments", "/blog/first-post/comments/1"));
+        assert!(matches("/docs/**/index", "/docs/a/b/index"));
+        assert!(matches("/v?/status.json", "/v2/status.json"));
+        assert!(!matches("/v?/status.json", "/v2/statusxjson"));
+    }
+}
@@ -1,65 +1,230 @@
-use std::collections::HashSet;
+use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::Arc;
 
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
 use axum::Router;
 use axum::routing::any;
-use config::Config;
-use tokio::sync::RwLock;
 use tracing::info;
 
-use crate::config::WebSimConfig;
+use crate::admin::{self, ADMIN_PREFIX};
+use crate::backend::{Backends, LlmBackend, build_backends};
+use crate::config::{BackendConfig, MOCK_BACKEND, WebSimConfig};
 use crate::db::Database;
+use crate::eviction;
+use crate::fixtures::{Fixtures, RecordingBackend, ReplayBackend};
 use crate::handler::handle;
-use crate::openrouter::OpenRouterClient;
+use crate::in_flight::InFlight;
+use crate::prefetch::{self, Prefetcher};
+use crate::prompt::PromptTemplates;
+use crate::routes::Routes;
+use crate::sites::{Sites, dispatcher};
 use crate::state::AppState;
 
-pub async fn run_server(db_path: Option<PathBuf>, config_path: PathBuf) -> Result<()> {
-    // Load configuration
-    let config_str = config_path.display().to_string();
-    let config = Config::builder()
-        .add_source(config::File::with_name(&config_str))
-        .build()
-        .with_context(|| format!("Failed to load config from: {}", config_str))?;
+/// Selects how content is generated, overriding the configured backends for tests and demos
+#[derive(Debug, Clone, Default)]
+pub enum BackendMode {
+    /// Use the backends from the config file
+    #[default]
+    Configured,
+    /// Generate every content type with the offline mock backend
+    Mock,
+    /// Use the configured backends, recording every response to a fixture file
+    Record(PathBuf),
+    /// Serve responses only from a fixture file, failing on requests that were never recorded
+    Replay(PathBuf),
+}
+
+/// Builds the application from a config file
+pub async fn build_app(
+    db_path: Option<PathBuf>,
+    config_path: PathBuf,
+    mode: BackendMode,
+) -> Result<Router> {
+    Ok(dispatcher(build_sites(db_path, config_path, mode).await?))
+}
 
-    let websim_config: WebSimConfig = config
-        .try_deserialize()
-        .with_context(|| format!("Failed to parse config from: {}", config_str))?;
+/// Loads the config file, opens the database and backends it refers to, and builds the state of each site
+async fn build_sites(
+    db_path: Option<PathBuf>,
+    config_path: PathBuf,
+    mode: BackendMode,
+) -> Result<Arc<Sites>> {
+    let mut websim_config = WebSimConfig::load(&config_path)?;
 
     info!(
-        "Loaded config from {} with {} content types",
-        config_str,
-        websim_config.content_types.len()
+        "Loaded config from {} with {} content types and {} other sites",
+        config_path.display(),
+        websim_config.content_types.len(),
+        websim_config.sites.len()
     );
 
+    if matches!(mode, BackendMode::Mock) {
+        info!("Using mock backend for all content types");
+        websim_config.backends = HashMap::from([(
+            MOCK_BACKEND.to_string(),
+            BackendConfig::Mock { delay_ms: 0 },
+        )]);
+        let site_content_types = websim_config
+            .sites
+            .values_mut()
+            .filter_map(|site| site.content_types.as_mut());
+        for content_types in
+            std::iter::once(&mut websim_config.content_types).chain(site_content_types)
+        {
+            for ct_config in content_types.values_mut() {
+                ct_config.backend = MOCK_BACKEND.to_string();
+            }
+        }
+        let site_routes = websim_config
+            .sites
+            .values_mut()
+            .map(|site| &mut site.routes);
+        for routes in std::iter::once(&mut websim_config.routes).chain(site_routes) {
+            for route in routes {
+                route.backend = None;
+            }
+        }
+    }
+
+    // Initialize database
+    let db = Database::new(db_path)?;
+
+    // Initialize chat completion backends
+    let backends: Backends = match mode {
+        BackendMode::Configured | BackendMode::Mock => build_backends(&websim_config.backends)?,
+        BackendMode::Record(path) => {
+            info!("Recording responses to fixtures at {}", path.display());
+            let fixtures = Arc::new(Fixtures::load(path)?);
+            build_backends(&websim_config.backends)?
+                .into_iter()
+                .map(|(name, backend)| {
+                    let recording: Arc<dyn LlmBackend> =
+                        Arc::new(RecordingBackend::new(backend, Arc::clone(&fixtures)));
+                    (name, recording)
+                })
+                .collect()
+        }
+        BackendMode::Replay(path) => {
+            info!("Replaying responses from fixtures at {}", path.display());
+            let replay: Arc<dyn LlmBackend> =
+                Arc::new(ReplayBackend::new(Arc::new(Fixtures::load(path)?)));
+            websim_config
+                .backends
+                .keys()
+                .map(|name| (name.clone(), Arc::clone(&replay)))
+                .collect()
+        }
+    };
+
+    let mut sites = Sites::new(build_state(
+        websim_config.clone(),
+        db.for_site(""),
+        backends.clone(),
+    )?);
+    for (name, site_config) in &websim_config.sites {
+        info!(
+            "Site {} (hosts: {}, path prefix: {})",
+            name,
+            site_config.hosts.join(", "),
+            site_config.path_prefix.as_deref().unwrap_or("none")
+        );
+        let state = build_state(
+            websim_config.for_site(site_config),
+            db.for_site(name),
+            backends.clone(),
+        )
+        .with_context(|| format!("Invalid site: {}", name))?;
+        sites.add(name, site_config, state)?;
+    }
+
+    Ok(Arc::new(sites))
+}
+
+/// Validates a site's config and builds its state, starting its background prefetching
+fn build_state(
+    websim_config: WebSimConfig,
+    db: Database,
+    backends: Backends,
+) -> Result<Arc<AppState>> {
     // Log configured content types
     for (mime_type, ct_config) in &websim_config.content_types {
         info!(
-            "  {} -> {} (model: {}, extensions: {})",
+            "  {} -> {} (backend: {}, model: {}, extensions: {})",
             mime_type,
             ct_config.content_type_header,
+            ct_config.backend,
             ct_config.model,
             ct_config.extensions.join(", ")
         );
+
+        if !websim_config.backends.contains_key(&ct_config.backend) {
+            bail!(
+                "Content type {} refers to unknown backend: {}",
+                mime_type,
+                ct_config.backend
+            );
+        }
     }
 
-    // Initialize database
-    let db = Database::new(db_path)?;
+    let routes = Routes::compile(&websim_config)?;
+    if !websim_config.routes.is_empty() {
+        info!("Loaded {} routes", websim_config.routes.len());
+    }
 
-    // Initialize OpenRouter client
-    let api_key = std::env::var("WEBSIM_API_KEY")
-        .with_context(|| "WEBSIM_API_KEY environment variable must be set")?;
-    let openrouter_client = OpenRouterClient::new(api_key.into());
+    let prompts = PromptTemplates::compile(&websim_config)?;
+
+    let (prefetcher, prefetch_queue) = Prefetcher::new(websim_config.prefetch.queue_size);
+    let prefetch_concurrency = websim_config.prefetch.concurrency;
 
     let state = Arc::new(AppState {
         db,
         config: websim_config,
-        openrouter_client,
-        in_flight: RwLock::new(HashSet::new()),
+        routes,
+        prompts,
+        backends,
+        in_flight: InFlight::default(),
+        prefetcher,
     });
 
-    let app = Router::new().fallback(any(handle)).with_state(state);
+    // Generate linked pages in the background for content types that opt in.
+    // The task holds a weak reference, so it stops once the application is dropped.
+    tokio::task::Builder::new()
+        .name("prefetch-queue")
+        .spawn(prefetch::run(
+            Arc::downgrade(&state),
+            prefetch_queue,
+            prefetch_concurrency,
+        ))?;
+
+    Ok(state)
+}
+
+/// Application serving a single site. Sites served under a path prefix also have their admin endpoints
+/// under it, as requests to [`ADMIN_PREFIX`] itself go to the site serving the host.
+pub fn router(state: Arc<AppState>, path_prefix: Option<&str>) -> Router {
+    let mut router = Router::new().nest(ADMIN_PREFIX, admin::router());
+    if let Some(prefix) = path_prefix {
+        router = router.nest(&format!("{}{}", prefix, ADMIN_PREFIX), admin::router());
+    }
+    router.fallback(any(handle)).with_state(state)
+}
+
+pub async fn run_server(
+    db_path: Option<PathBuf>,
+    config_path: PathBuf,
+    mode: BackendMode,
+) -> Result<()> {
+    let sites = build_sites(db_path, config_path, mode).await?;
+
+    // Enforce cache TTLs and the size cap of each site in the background
+    for site in sites.iter() {
+        tokio::task::Builder::new()
+            .name("cache-eviction")
+            .spawn(eviction::run(Arc::clone(&site.state)))?;
+    }
+
+    let app = dispatcher(sites);
 
     let listener = tokio::net::TcpListener::bind("localhost:3000").await?;
     info!("Server running on http://localhost:3000");
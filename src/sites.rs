@@ -0,0 +1,172 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Result, bail};
+use axum::Router;
+use axum::extract::{Request, State};
+use axum::http::header;
+use axum::http::uri::Authority;
+use axum::response::Response;
+use tower::ServiceExt;
+
+use crate::config::{SiteConfig, WebSimConfig};
+use crate::db::Database;
+use crate::server::router;
+use crate::state::AppState;
+
+/// Which requests a site serves
+#[derive(Debug, Default)]
+struct SiteMatcher {
+    /// Lowercase host names, without ports
+    hosts: Vec<String>,
+    /// Path prefix without a trailing slash
+    path_prefix: Option<String>,
+}
+
+impl SiteMatcher {
+    fn new(config: &SiteConfig) -> Self {
+        Self {
+            hosts: config
+                .hosts
+                .iter()
+                .map(|host| host.to_ascii_lowercase())
+                .collect(),
+            path_prefix: config
+                .path_prefix
+                .as_deref()
+                .map(|prefix| prefix.trim_end_matches('/').to_string()),
+        }
+    }
+
+    /// Whether a request for `path` with the `host` it was sent to (possibly with a port) belongs to the site
+    fn matches(&self, host: Option<&str>, path: &str) -> bool {
+        let host = host
+            .and_then(|host| host.parse::<Authority>().ok())
+            .map(|authority| authority.host().to_ascii_lowercase());
+        if host.is_some_and(|host| self.hosts.contains(&host)) {
+            return true;
+        }
+
+        self.path_prefix.as_deref().is_some_and(|prefix| {
+            path.strip_prefix(prefix)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        })
+    }
+}
+
+/// A site and the application serving it
+pub struct Site {
+    matcher: SiteMatcher,
+    pub state: Arc<AppState>,
+    app: Router,
+}
+
+/// Every site a server runs, the default one serving requests no other site matches
+pub struct Sites {
+    default: Site,
+    others: Vec<Site>,
+}
+
+impl Sites {
+    pub fn new(default: Arc<AppState>) -> Self {
+        Self {
+            default: Site {
+                matcher: SiteMatcher::default(),
+                app: router(Arc::clone(&default), None),
+                state: default,
+            },
+            others: Vec::new(),
+        }
+    }
+
+    /// Adds a site, matched after those already added
+    pub fn add(&mut self, name: &str, config: &SiteConfig, state: Arc<AppState>) -> Result<()> {
+        if name.is_empty() {
+            bail!("Sites need a name");
+        }
+        if config.hosts.is_empty() && config.path_prefix.is_none() {
+            bail!(
+                "Site {} needs `hosts` or a `path_prefix` to be served on",
+                name
+            );
+        }
+
+        let matcher = SiteMatcher::new(config);
+        if matcher
+            .path_prefix
+            .as_deref()
+            .is_some_and(|prefix| !prefix.starts_with('/') || prefix.len() < 2)
+        {
+            bail!("Site {} needs a `path_prefix` below `/`, e.g. `/api`", name);
+        }
+
+        self.others.push(Site {
+            app: router(Arc::clone(&state), matcher.path_prefix.as_deref()),
+            matcher,
+            state,
+        });
+        Ok(())
+    }
+
+    /// Every site, starting with the default one
+    pub fn iter(&self) -> impl Iterator<Item = &Site> {
+        std::iter::once(&self.default).chain(&self.others)
+    }
+
+    /// The site serving a request
+    fn select(&self, host: Option<&str>, path: &str) -> &Site {
+        self.others
+            .iter()
+            .find(|site| site.matcher.matches(host, path))
+            .unwrap_or(&self.default)
+    }
+}
+
+/// Opens a database file scoped to the stored resources of `site`, which must be configured unless
+/// it is the default site, `""`
+pub fn open_site_database(db_path: PathBuf, config_path: &Path, site: &str) -> Result<Database> {
+    if !site.is_empty() {
+        WebSimConfig::load(config_path)?.site(site)?;
+    }
+    Ok(Database::new(Some(db_path))?.for_site(site))
+}
+
+/// Application dispatching each request to the site serving it
+pub fn dispatcher(sites: Arc<Sites>) -> Router {
+    Router::new().fallback(dispatch).with_state(sites)
+}
+
+async fn dispatch(State(sites): State<Arc<Sites>>, req: Request) -> Response {
+    let host = req
+        .uri()
+        .host()
+        .or_else(|| req.headers().get(header::HOST)?.to_str().ok());
+    let site = sites.select(host, req.uri().path());
+
+    let Ok(response) = site.app.clone().oneshot(req).await;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_site_matcher() {
+        let matcher = SiteMatcher::new(&SiteConfig {
+            hosts: vec!["News.localhost".to_string()],
+            path_prefix: Some("/api/".to_string()),
+            world: None,
+            content_types: None,
+            routes: Vec::new(),
+        });
+
+        assert!(matcher.matches(Some("news.localhost:3000"), "/"));
+        assert!(matcher.matches(Some("NEWS.localhost"), "/articles"));
+        assert!(!matcher.matches(Some("localhost:3000"), "/"));
+        assert!(!matcher.matches(None, "/"));
+        assert!(matcher.matches(Some("localhost:3000"), "/api"));
+        assert!(matcher.matches(None, "/api/fruits"));
+        assert!(!matcher.matches(None, "/apis"));
+    }
+}
@@ -1,16 +1,23 @@
-use std::collections::HashSet;
-
-use tokio::sync::RwLock;
-
+use crate::backend::Backends;
 use crate::config::WebSimConfig;
 use crate::db::Database;
-use crate::openrouter::OpenRouterClient;
+use crate::in_flight::InFlight;
+use crate::prefetch::Prefetcher;
+use crate::prompt::PromptTemplates;
+use crate::routes::Routes;
 
 /// Shared application state
 pub struct AppState {
     pub db: Database,
     pub config: WebSimConfig,
-    pub openrouter_client: OpenRouterClient,
-    /// Tracks in-flight requests to prevent duplicate generation for the same path
-    pub in_flight: RwLock<HashSet<String>>,
+    /// Overrides for matching paths, compiled from `config.routes`
+    pub routes: Routes,
+    /// User prompt templates, compiled from the config
+    pub prompts: PromptTemplates,
+    /// Chat completion backends keyed by the name content types refer to them by
+    pub backends: Backends,
+    /// Tracks in-flight requests so concurrent requests for the same path share one generation
+    pub in_flight: InFlight,
+    /// Queues pages linked from generated HTML to generate in the background
+    pub prefetcher: Prefetcher,
 }
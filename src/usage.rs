@@ -0,0 +1,153 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::AddAssign;
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::db::{Database, UsageSummary};
+
+/// Token and cost totals over a set of generations
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct UsageTotals {
+    pub generations: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    /// Cost in US dollars, counting generations without a reported cost as free
+    pub cost: f64,
+}
+
+impl AddAssign for UsageTotals {
+    fn add_assign(&mut self, other: Self) {
+        self.generations += other.generations;
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.cost += other.cost;
+    }
+}
+
+/// Aggregate usage per model, content type and path prefix
+#[derive(Debug, Default, Serialize)]
+pub struct UsageReport {
+    pub total: UsageTotals,
+    pub by_model: BTreeMap<String, UsageTotals>,
+    pub by_content_type: BTreeMap<String, UsageTotals>,
+    pub by_path_prefix: BTreeMap<String, UsageTotals>,
+}
+
+impl UsageReport {
+    /// Builds a report, grouping paths by their first `depth` segments
+    pub fn new(summaries: &[UsageSummary], depth: usize) -> Self {
+        let mut report = Self::default();
+        for summary in summaries {
+            report.total += summary.totals;
+            *report.by_model.entry(summary.model.clone()).or_default() += summary.totals;
+            *report
+                .by_content_type
+                .entry(summary.content_type.clone())
+                .or_default() += summary.totals;
+            *report
+                .by_path_prefix
+                .entry(path_prefix(&summary.path, depth))
+                .or_default() += summary.totals;
+        }
+        report
+    }
+
+    /// Builds a report from the usage recorded in a database
+    pub async fn load(db: &Database, depth: usize) -> Result<Self> {
+        Ok(Self::new(&db.usage_summary().await?, depth))
+    }
+}
+
+/// Builds a report of the usage recorded for the database's site
+pub async fn usage_report(db: &Database, depth: usize) -> Result<UsageReport> {
+    UsageReport::load(db, depth).await
+}
+
+/// Truncates a path to its first `depth` segments, e.g. `/blog/posts/1` to `/blog` for a depth of 1
+fn path_prefix(path: &str, depth: usize) -> String {
+    let segments: Vec<&str> = path
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .take(depth)
+        .collect();
+    format!("/{}", segments.join("/"))
+}
+
+impl fmt::Display for UsageReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sections = [
+            ("Model", &self.by_model),
+            ("Content type", &self.by_content_type),
+            ("Path prefix", &self.by_path_prefix),
+        ];
+
+        for (heading, rows) in sections {
+            writeln!(
+                f,
+                "{:<40} {:>8} {:>12} {:>12} {:>10}",
+                heading, "Count", "Prompt", "Completion", "Cost ($)"
+            )?;
+            for (key, totals) in rows {
+                write_totals(f, key, totals)?;
+            }
+            writeln!(f)?;
+        }
+
+        write_totals(f, "Total", &self.total)
+    }
+}
+
+fn write_totals(f: &mut fmt::Formatter<'_>, label: &str, totals: &UsageTotals) -> fmt::Result {
+    writeln!(
+        f,
+        "{:<40} {:>8} {:>12} {:>12} {:>10.4}",
+        label, totals.generations, totals.prompt_tokens, totals.completion_tokens, totals.cost
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_path_prefix() {
+        assert_eq!(path_prefix("/blog/posts/1", 1), "/blog");
+        assert_eq!(path_prefix("/blog/posts/1", 2), "/blog/posts");
+        assert_eq!(path_prefix("/blog", 3), "/blog");
+        assert_eq!(path_prefix("/", 1), "/");
+        assert_eq!(path_prefix("/blog/posts", 0), "/");
+    }
+
+    #[test]
+    fn test_report_groups_summaries() {
+        let summary = |model: &str, content_type: &str, path: &str, cost: f64| UsageSummary {
+            model: model.to_string(),
+            content_type: content_type.to_string(),
+            path: path.to_string(),
+            totals: UsageTotals {
+                generations: 1,
+                prompt_tokens: 100,
+                completion_tokens: 10,
+                cost,
+            },
+        };
+
+        let report = UsageReport::new(
+            &[
+                summary("a", "text/html", "/blog/1", 0.5),
+                summary("a", "image/svg+xml", "/blog/1.svg", 0.25),
+                summary("b", "text/html", "/about", 1.0),
+            ],
+            1,
+        );
+
+        assert_eq!(report.total.generations, 3);
+        assert_eq!(report.total.cost, 1.75);
+        assert_eq!(report.by_model["a"].prompt_tokens, 200);
+        assert_eq!(report.by_content_type["text/html"].cost, 1.5);
+        assert_eq!(report.by_path_prefix["/blog"].generations, 2);
+        assert_eq!(report.by_path_prefix["/about"].completion_tokens, 10);
+    }
+}
@@ -0,0 +1,70 @@
+use anyhow::{Context, Result};
+use similar::TextDiff;
+
+/// Query parameter selecting a stored version of a resource, e.g. `/apples?__version=2`
+pub const VERSION_PARAM: &str = "__version";
+
+/// Splits the version parameter from a query string, returning the requested version and the remaining query.
+/// The remaining parameters keep their original order and encoding.
+pub fn split_version_param(query: &str) -> Result<(Option<u32>, String)> {
+    let mut version = None;
+    let mut rest = Vec::new();
+
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        match pair.split_once('=') {
+            Some((VERSION_PARAM, value)) => {
+                version = Some(
+                    value
+                        .parse()
+                        .with_context(|| format!("Invalid {}: {}", VERSION_PARAM, value))?,
+                );
+            }
+            _ => rest.push(pair),
+        }
+    }
+
+    Ok((version, rest.join("&")))
+}
+
+/// Renders a unified diff between two versions of a resource
+pub fn diff(label: &str, from: (u32, &str), to: (u32, &str)) -> String {
+    TextDiff::from_lines(from.1, to.1)
+        .unified_diff()
+        .context_radius(3)
+        .header(
+            &format!("{} (version {})", label, from.0),
+            &format!("{} (version {})", label, to.0),
+        )
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_version_param() {
+        assert_eq!(split_version_param("").unwrap(), (None, String::new()));
+        assert_eq!(
+            split_version_param("color=green").unwrap(),
+            (None, "color=green".to_string())
+        );
+        assert_eq!(
+            split_version_param("__version=3").unwrap(),
+            (Some(3), String::new())
+        );
+        assert_eq!(
+            split_version_param("color=green&__version=2&size=big%20one").unwrap(),
+            (Some(2), "color=green&size=big%20one".to_string())
+        );
+        assert!(split_version_param("__version=latest").is_err());
+    }
+
+    #[test]
+    fn test_diff() {
+        let diff = diff("/apples", (1, "a\nb\nc\n"), (2, "a\nB\nc\n"));
+        assert!(diff.contains("--- /apples (version 1)"));
+        assert!(diff.contains("+++ /apples (version 2)"));
+        assert!(diff.contains("-b\n+B\n"));
+    }
+}
@@ -0,0 +1,451 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, SecondsFormat, Utc};
+use flate2::Compression;
+use flate2::read::{MultiGzDecoder, ZlibDecoder};
+use flate2::write::GzEncoder;
+use sha2::{Digest, Sha256};
+use tracing::warn;
+use url::Url;
+
+use crate::db::{Database, Resource};
+use crate::utils::normalize_path;
+
+/// Host archived responses are attributed to when no base URL is given
+pub const DEFAULT_BASE_URL: &str = "http://localhost:3000";
+
+/// A resource imported from a WARC response record
+#[derive(Debug)]
+pub struct ImportedRecord {
+    pub uri: String,
+    pub path: String,
+    pub query: String,
+    pub mime_type: String,
+    pub version: u32,
+}
+
+/// Header fields in the order they appear, looked up case-insensitively
+type Headers = Vec<(String, String)>;
+
+/// A WARC record's named fields and content block
+#[derive(Debug)]
+struct Record {
+    headers: Headers,
+    block: Vec<u8>,
+}
+
+impl Record {
+    fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+}
+
+/// Writes every resource being served to a WARC file as a request and response record pair, as if
+/// requested from `base_url`. Records are compressed individually if `out` ends in `.gz`, as replay
+/// tools expect. Returns the number of resources archived.
+pub async fn export_warc(db: &Database, out: &Path, base_url: &str) -> Result<usize> {
+    let base = Url::parse(base_url).with_context(|| format!("Invalid base URL: {}", base_url))?;
+    let resources = db.resources().await?;
+
+    let compress = out.extension().is_some_and(|ext| ext == "gz");
+    let file = File::create(out).with_context(|| format!("Failed to create {}", out.display()))?;
+    let mut writer = BufWriter::new(file);
+
+    let info = format!(
+        "software: websim/{}\r\nformat: WARC File Format 1.1\r\n",
+        env!("CARGO_PKG_VERSION")
+    );
+    write_record(
+        &mut writer,
+        compress,
+        &[
+            ("WARC-Type", "warcinfo".to_string()),
+            ("WARC-Record-ID", record_id(&["warcinfo", base_url])),
+            ("WARC-Date", warc_date(Utc::now())),
+            ("Content-Type", "application/warc-fields".to_string()),
+        ],
+        info.as_bytes(),
+    )?;
+
+    for resource in &resources {
+        write_exchange(&mut writer, compress, &base, resource)?;
+    }
+    writer.flush()?;
+
+    Ok(resources.len())
+}
+
+/// Writes the synthesized request and response records for a resource
+fn write_exchange(
+    writer: &mut impl Write,
+    compress: bool,
+    base: &Url,
+    resource: &Resource,
+) -> Result<()> {
+    let mut uri = base.join(&resource.path)?;
+    uri.set_query(
+        Some(&resource.query)
+            .filter(|q| !q.is_empty())
+            .map(|q| q.as_str()),
+    );
+    let date = resource
+        .updated_at
+        .and_then(|secs| DateTime::from_timestamp(secs, 0))
+        .unwrap_or_else(Utc::now);
+    let version = resource.version.to_string();
+    let key = [uri.as_str(), resource.mime_type.as_str(), version.as_str()];
+    let request_id = record_id(&[&["request"], &key[..]].concat());
+    let response_id = record_id(&[&["response"], &key[..]].concat());
+
+    let response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: {}; charset=utf-8\r\nContent-Length: {}\r\nDate: {}\r\nLast-Modified: {}\r\n\r\n",
+        resource.mime_type,
+        resource.content.len(),
+        httpdate::fmt_http_date(date.into()),
+        httpdate::fmt_http_date(date.into()),
+    );
+    write_record(
+        writer,
+        compress,
+        &[
+            ("WARC-Type", "response".to_string()),
+            ("WARC-Record-ID", response_id.clone()),
+            ("WARC-Date", warc_date(date)),
+            ("WARC-Target-URI", uri.to_string()),
+            (
+                "Content-Type",
+                "application/http; msgtype=response".to_string(),
+            ),
+        ],
+        &[response.as_bytes(), resource.content.as_bytes()].concat(),
+    )?;
+
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nAccept: {}\r\n\r\n",
+        &uri[url::Position::BeforePath..],
+        &uri[url::Position::BeforeHost..url::Position::AfterPort],
+        resource.mime_type,
+    );
+    write_record(
+        writer,
+        compress,
+        &[
+            ("WARC-Type", "request".to_string()),
+            ("WARC-Record-ID", request_id),
+            ("WARC-Date", warc_date(date)),
+            ("WARC-Target-URI", uri.to_string()),
+            ("WARC-Concurrent-To", response_id),
+            (
+                "Content-Type",
+                "application/http; msgtype=request".to_string(),
+            ),
+        ],
+        request.as_bytes(),
+    )
+}
+
+/// Imports the successful text responses in a WARC file, such as one written by a crawler, as hand-written
+/// content. Only responses from `host` are imported, defaulting to the host of the first response.
+pub async fn import_warc(
+    db: &Database,
+    file: &Path,
+    host: Option<&str>,
+) -> Result<Vec<ImportedRecord>> {
+    let data = fs::read(file).with_context(|| format!("Failed to read {}", file.display()))?;
+    let records = read_records(&data)?;
+
+    let mut host = host.map(str::to_string);
+    let mut imported = Vec::new();
+    for record in &records {
+        if record.header("WARC-Type") != Some("response") {
+            continue;
+        }
+        let Some(uri) = record
+            .header("WARC-Target-URI")
+            .map(|uri| uri.trim_start_matches('<').trim_end_matches('>'))
+            .and_then(|uri| Url::parse(uri).ok())
+        else {
+            continue;
+        };
+        let record_host = uri.host_str().unwrap_or_default();
+        if *host.get_or_insert_with(|| record_host.to_string()) != record_host {
+            continue;
+        }
+
+        let Some((status, headers, body)) = parse_http_response(&record.block) else {
+            warn!(uri = %uri, "Skipping record without an HTTP response");
+            continue;
+        };
+        if !(200..300).contains(&status) {
+            continue;
+        }
+        let mime_type = find_header(&headers, "Content-Type")
+            .and_then(|value| value.split(';').next())
+            .map(|essence| essence.trim().to_ascii_lowercase())
+            .unwrap_or_else(|| "text/html".to_string());
+
+        let body = match decode_body(&headers, body) {
+            Ok(body) => body,
+            Err(e) => {
+                warn!(uri = %uri, error = %e, "Skipping response that couldn't be decoded");
+                continue;
+            }
+        };
+        let Ok(content) = String::from_utf8(body) else {
+            warn!(uri = %uri, mime_type = %mime_type, "Skipping response that isn't UTF-8 text");
+            continue;
+        };
+
+        let path = normalize_path(uri.path()).to_string();
+        let query = uri.query().unwrap_or("").to_string();
+        let version = db.import(&path, &query, &mime_type, &content).await?;
+        imported.push(ImportedRecord {
+            uri: uri.to_string(),
+            path,
+            query,
+            mime_type,
+            version,
+        });
+    }
+
+    Ok(imported)
+}
+
+/// Writes a WARC 1.1 record, adding its length, gzipped as a member of its own if `compress` is set
+fn write_record(
+    writer: &mut impl Write,
+    compress: bool,
+    headers: &[(&str, String)],
+    block: &[u8],
+) -> Result<()> {
+    let mut record = b"WARC/1.1\r\n".to_vec();
+    for (name, value) in headers {
+        record.extend_from_slice(format!("{}: {}\r\n", name, value).as_bytes());
+    }
+    record.extend_from_slice(format!("Content-Length: {}\r\n\r\n", block.len()).as_bytes());
+    record.extend_from_slice(block);
+    record.extend_from_slice(b"\r\n\r\n");
+
+    if compress {
+        let mut encoder = GzEncoder::new(writer, Compression::default());
+        encoder.write_all(&record)?;
+        encoder.finish()?;
+    } else {
+        writer.write_all(&record)?;
+    }
+    Ok(())
+}
+
+/// Parses the records in a WARC file, which may be gzipped
+fn read_records(data: &[u8]) -> Result<Vec<Record>> {
+    let data = if data.starts_with(&[0x1f, 0x8b]) {
+        let mut decompressed = Vec::new();
+        MultiGzDecoder::new(data).read_to_end(&mut decompressed)?;
+        decompressed
+    } else {
+        data.to_vec()
+    };
+
+    let mut records = Vec::new();
+    let mut rest = data.as_slice();
+    loop {
+        while let Some(stripped) = rest.strip_prefix(b"\r\n") {
+            rest = stripped;
+        }
+        if rest.is_empty() {
+            break;
+        }
+
+        let Some((head, after)) = split_head(rest) else {
+            bail!("Truncated WARC record header");
+        };
+        let mut lines = head.split("\r\n");
+        if !lines.next().is_some_and(|line| line.starts_with("WARC/")) {
+            bail!("Not a WARC record");
+        }
+        let headers = parse_headers(lines);
+        let length: usize = find_header(&headers, "Content-Length")
+            .context("WARC record without a Content-Length")?
+            .parse()
+            .context("Invalid WARC Content-Length")?;
+        if after.len() < length {
+            bail!("Truncated WARC record");
+        }
+
+        records.push(Record {
+            headers,
+            block: after[..length].to_vec(),
+        });
+        rest = &after[length..];
+    }
+
+    Ok(records)
+}
+
+/// Parses an HTTP response into its status, headers and raw body
+fn parse_http_response(block: &[u8]) -> Option<(u16, Headers, &[u8])> {
+    let (head, body) = split_head(block)?;
+    let mut lines = head.split("\r\n");
+    let status = lines
+        .next()?
+        .strip_prefix("HTTP/")?
+        .split_whitespace()
+        .nth(1)?
+        .parse()
+        .ok()?;
+    Some((status, parse_headers(lines), body))
+}
+
+/// Undoes the chunked transfer coding and gzip or deflate content coding crawlers store bodies with
+fn decode_body(headers: &[(String, String)], body: &[u8]) -> Result<Vec<u8>> {
+    let has_coding = |name: &str, coding: &str| {
+        find_header(headers, name).is_some_and(|value| value.to_ascii_lowercase().contains(coding))
+    };
+
+    let mut body = if has_coding("Transfer-Encoding", "chunked") {
+        dechunk(body)?
+    } else {
+        body.to_vec()
+    };
+
+    if has_coding("Content-Encoding", "gzip") {
+        let mut decoded = Vec::new();
+        MultiGzDecoder::new(body.as_slice()).read_to_end(&mut decoded)?;
+        body = decoded;
+    } else if has_coding("Content-Encoding", "deflate") {
+        let mut decoded = Vec::new();
+        ZlibDecoder::new(body.as_slice()).read_to_end(&mut decoded)?;
+        body = decoded;
+    } else if let Some(coding) =
+        find_header(headers, "Content-Encoding").filter(|c| !c.eq_ignore_ascii_case("identity"))
+    {
+        bail!("Unsupported content coding: {}", coding);
+    }
+
+    Ok(body)
+}
+
+/// Joins the chunks of a body sent with chunked transfer coding
+fn dechunk(mut body: &[u8]) -> Result<Vec<u8>> {
+    let mut joined = Vec::new();
+    loop {
+        let line_end = body
+            .windows(2)
+            .position(|w| w == b"\r\n")
+            .context("Truncated chunk size")?;
+        let size_line = std::str::from_utf8(&body[..line_end])?;
+        let size_hex = size_line.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size_hex, 16)
+            .with_context(|| format!("Invalid chunk size: {}", size_hex))?;
+        body = &body[line_end + 2..];
+        if size == 0 {
+            return Ok(joined);
+        }
+        if body.len() < size {
+            bail!("Truncated chunk");
+        }
+        joined.extend_from_slice(&body[..size]);
+        body = body[size..].strip_prefix(b"\r\n").unwrap_or(&body[size..]);
+    }
+}
+
+/// Splits a header section ending in a blank line from what follows it
+fn split_head(data: &[u8]) -> Option<(&str, &[u8])> {
+    let end = data.windows(4).position(|w| w == b"\r\n\r\n")?;
+    let head = std::str::from_utf8(&data[..end]).ok()?;
+    Some((head, &data[end + 4..]))
+}
+
+fn parse_headers<'a>(lines: impl Iterator<Item = &'a str>) -> Headers {
+    lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .collect()
+}
+
+fn find_header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+/// Record ID in the UUID URN form replay tools expect, derived from `parts` so exports are reproducible
+fn record_id(parts: &[&str]) -> String {
+    let digest = Sha256::digest(parts.join("\n").as_bytes());
+    let hex: String = digest[..16].iter().map(|b| format!("{:02x}", b)).collect();
+    format!(
+        "<urn:uuid:{}-{}-{}-{}-{}>",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// Formats a timestamp as a WARC date, e.g. `2024-05-01T12:00:00Z`
+fn warc_date(date: DateTime<Utc>) -> String {
+    date.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reads_written_records() {
+        for compress in [false, true] {
+            let mut data = Vec::new();
+            for (id, block) in [("1", "first\r\n\r\nblock"), ("2", "")] {
+                write_record(
+                    &mut data,
+                    compress,
+                    &[
+                        ("WARC-Type", "resource".to_string()),
+                        ("WARC-Record-ID", id.to_string()),
+                    ],
+                    block.as_bytes(),
+                )
+                .unwrap();
+            }
+
+            let records = read_records(&data).unwrap();
+            assert_eq!(records.len(), 2);
+            assert_eq!(records[0].header("warc-record-id"), Some("1"));
+            assert_eq!(records[0].block, b"first\r\n\r\nblock");
+            assert_eq!(records[1].header("Content-Length"), Some("0"));
+        }
+    }
+
+    #[test]
+    fn test_decodes_crawled_response() {
+        let mut gzipped = GzEncoder::new(Vec::new(), Compression::default());
+        gzipped.write_all(b"<h1>Hello</h1>").unwrap();
+        let gzipped = gzipped.finish().unwrap();
+
+        let mut block = b"HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Encoding: gzip\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+        for chunk in gzipped.chunks(10) {
+            block.extend_from_slice(format!("{:x}\r\n", chunk.len()).as_bytes());
+            block.extend_from_slice(chunk);
+            block.extend_from_slice(b"\r\n");
+        }
+        block.extend_from_slice(b"0\r\n\r\n");
+
+        let (status, headers, body) = parse_http_response(&block).unwrap();
+        assert_eq!(status, 200);
+        assert_eq!(find_header(&headers, "content-type"), Some("text/html"));
+        assert_eq!(decode_body(&headers, body).unwrap(), b"<h1>Hello</h1>");
+    }
+
+    #[test]
+    fn test_record_id_is_a_uuid_urn() {
+        let id = record_id(&["response", "http://localhost:3000/"]);
+        assert_eq!(id, record_id(&["response", "http://localhost:3000/"]));
+        assert!(id.starts_with("<urn:uuid:") && id.ends_with('>'));
+        assert_eq!(id.len(), "<urn:uuid:>".len() + 36);
+    }
+}
@@ -0,0 +1,72 @@
+//! Helpers shared by the integration tests
+
+use std::path::PathBuf;
+
+use websim::BackendMode;
+
+/// Config generating every content type with the mock backend
+pub const CONFIG: &str = r#"
+backends:
+  mock:
+    type: mock
+    delay_ms: 300
+
+admin:
+  token: secret
+
+content_types:
+  text/html:
+    backend: mock
+    model: mock
+    system_prompt: html
+    content_type_header: "text/html; charset=utf-8"
+    extensions: [html]
+    cache_control: no-cache
+  image/svg+xml:
+    backend: mock
+    model: mock
+    system_prompt: svg
+    content_type_header: "image/svg+xml"
+    extensions: [svg]
+  application/json:
+    backend: mock
+    model: mock
+    system_prompt: json
+    content_type_header: "application/json"
+    extensions: [json]
+"#;
+
+/// Returns a fresh path in the temp directory unique to this test
+pub fn temp_path(name: &str, extension: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "websim-{}-{}.{}",
+        std::process::id(),
+        name,
+        extension
+    ));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+/// Starts a server on an ephemeral port and returns its base URL
+pub async fn spawn_server(name: &str, db_path: Option<PathBuf>, mode: BackendMode) -> String {
+    spawn_server_with_config(name, db_path, mode, CONFIG).await
+}
+
+/// Starts a server with the given config on an ephemeral port and returns its base URL
+pub async fn spawn_server_with_config(
+    name: &str,
+    db_path: Option<PathBuf>,
+    mode: BackendMode,
+    config: &str,
+) -> String {
+    let config_path = temp_path(name, "yml");
+    std::fs::write(&config_path, config).unwrap();
+
+    let app = websim::build_app(db_path, config_path, mode).await.unwrap();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    format!("http://{}", addr)
+}
@@ -0,0 +1,41 @@
+//! Record and replay of backend responses through fixture files
+
+mod common;
+
+use common::{spawn_server, temp_path};
+use websim::BackendMode;
+
+async fn get(url: String) -> String {
+    reqwest::get(url).await.unwrap().text().await.unwrap()
+}
+
+#[tokio::test]
+async fn test_replays_recorded_responses() {
+    let fixtures_path = temp_path("fixtures", "json");
+
+    let recording = spawn_server("record", None, BackendMode::Record(fixtures_path.clone())).await;
+    let page = get(format!("{}/fruits/apples", recording)).await;
+    let image = get(format!("{}/fruits/apples.svg", recording)).await;
+
+    let fixtures: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&fixtures_path).unwrap()).unwrap();
+    assert_eq!(fixtures.as_object().unwrap().len(), 2);
+
+    // A fresh server replaying the fixtures serves identical content without calling a backend
+    let replaying = spawn_server("replay", None, BackendMode::Replay(fixtures_path)).await;
+    assert_eq!(get(format!("{}/fruits/apples", replaying)).await, page);
+    assert_eq!(get(format!("{}/fruits/apples.svg", replaying)).await, image);
+
+    // Requests that were never recorded fail
+    let missing = reqwest::get(format!("{}/fruits/pears", replaying))
+        .await
+        .unwrap();
+    assert_eq!(missing.status(), 502);
+    assert!(
+        missing
+            .text()
+            .await
+            .unwrap()
+            .contains("No fixture recorded")
+    );
+}
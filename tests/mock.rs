@@ -0,0 +1,1144 @@
+//! End-to-end tests against the offline mock backend
+
+mod common;
+
+use common::{spawn_server, spawn_server_with_config, temp_path};
+use rusqlite::Connection;
+use websim::{BackendMode, Database};
+
+/// Admin token set in `common::CONFIG`
+const ADMIN_TOKEN: &str = "secret";
+
+async fn get(url: String) -> String {
+    reqwest::get(url).await.unwrap().text().await.unwrap()
+}
+
+#[tokio::test]
+async fn test_generates_and_serves_from_cache() {
+    let db_path = temp_path("cache", "sqlite");
+    let base = spawn_server("cache", Some(db_path.clone()), BackendMode::Configured).await;
+
+    let response = reqwest::get(format!("{}/fruits/apples", base))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(
+        response.headers()["content-type"],
+        "text/html; charset=utf-8"
+    );
+    let body = response.text().await.unwrap();
+    assert!(body.contains("<h1>apples</h1>"));
+
+    // Later requests are served from the database rather than regenerated
+    let conn = Connection::open(&db_path).unwrap();
+    let updated = conn
+        .execute(
+            "UPDATE resources SET content = 'cached' WHERE path = '/fruits/apples'",
+            [],
+        )
+        .unwrap();
+    assert_eq!(updated, 1);
+
+    let body = reqwest::get(format!("{}/fruits/apples/", base))
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert_eq!(body, "cached");
+}
+
+#[tokio::test]
+async fn test_reference_materials_include_parent_and_base_pages() {
+    let base = spawn_server("references", None, BackendMode::Configured).await;
+    let client = reqwest::Client::new();
+
+    // Content is stored once the streamed body completes
+    client
+        .get(format!("{}/fruits", base))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    let body = client
+        .get(format!("{}/fruits/apples", base))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(body.contains("<!-- reference: /fruits (parent) -->"));
+
+    let body = client
+        .get(format!("{}/fruits/apples?color=green", base))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(body.contains("<!-- reference: /fruits/apples (base page) -->"));
+    assert!(body.contains("<!-- reference: /fruits (parent) -->"));
+}
+
+#[tokio::test]
+async fn test_concurrent_requests_share_one_generation() {
+    let base = spawn_server("in-flight", None, BackendMode::Configured).await;
+    let client = reqwest::Client::new();
+
+    let requests = (0..5).map(|_| {
+        let request = client.get(format!("{}/icons/logo.svg", base)).send();
+        async move {
+            let response = request.await.unwrap();
+            assert_eq!(response.status(), 200);
+            let validators = (
+                response.headers().get("etag").cloned(),
+                response.headers().get("last-modified").cloned(),
+            );
+            (validators, response.text().await.unwrap())
+        }
+    });
+    let responses = futures_util::future::join_all(requests).await;
+
+    let bodies: Vec<&str> = responses.iter().map(|(_, body)| body.as_str()).collect();
+    assert!(bodies[0].starts_with("<svg"));
+    assert!(bodies.iter().all(|body| body == &bodies[0]));
+
+    // Requests that waited on the generation get the same validators as a later cache hit
+    let hit = client
+        .get(format!("{}/icons/logo.svg", base))
+        .send()
+        .await
+        .unwrap();
+    let cached = (
+        hit.headers().get("etag").cloned(),
+        hit.headers().get("last-modified").cloned(),
+    );
+    assert!(cached.0.is_some() && cached.1.is_some());
+    let followers = responses
+        .iter()
+        .filter(|(validators, _)| validators.0.is_some())
+        .inspect(|(validators, _)| assert_eq!(*validators, cached))
+        .count();
+    assert!(followers >= 4, "{}", followers);
+}
+
+#[tokio::test]
+async fn test_post_generates_json_without_caching() {
+    let db_path = temp_path("post", "sqlite");
+    let base = spawn_server("post", Some(db_path.clone()), BackendMode::Configured).await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/contact", base))
+        .header("Content-Type", "application/json")
+        .body(r#"{"name": "John Doe"}"#)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["content-type"], "application/json");
+
+    let json: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(json["subject"], "contact");
+
+    let conn = Connection::open(&db_path).unwrap();
+    let count: i64 = conn
+        .query_row("SELECT COUNT(*) FROM resources", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(count, 0);
+}
+
+#[tokio::test]
+async fn test_accept_header_selects_content_type() {
+    let base = spawn_server("accept", None, BackendMode::Configured).await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/apples", base))
+        .header("Accept", "application/json")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.headers()["content-type"], "application/json");
+
+    let json: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(json["path"], "/apples");
+
+    // Weights are honoured over the order types are listed in
+    let response = reqwest::Client::new()
+        .get(format!("{}/apples", base))
+        .header("Accept", "text/html;q=0.1, application/json")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.headers()["content-type"], "application/json");
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/apples", base))
+        .header("Accept", "application/xml")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 406);
+}
+
+#[tokio::test]
+async fn test_usage_report_totals_generations() {
+    let base = spawn_server("usage", None, BackendMode::Configured).await;
+    let client = reqwest::Client::new();
+
+    for path in ["/fruits/apples", "/fruits/pears.svg", "/about"] {
+        client
+            .get(format!("{}{}", base, path))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+    }
+    client
+        .post(format!("{}/contact", base))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+
+    let report: serde_json::Value = client
+        .get(format!("{}/_websim/usage", base))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(report["total"]["generations"], 4);
+    assert!(report["total"]["completion_tokens"].as_u64().unwrap() > 0);
+    assert_eq!(report["by_model"]["mock"]["generations"], 4);
+    assert_eq!(report["by_content_type"]["image/svg+xml"]["generations"], 1);
+    assert_eq!(report["by_path_prefix"]["/fruits"]["generations"], 2);
+    assert_eq!(report["by_path_prefix"]["/contact"]["generations"], 1);
+}
+
+#[tokio::test]
+async fn test_exhausted_budget_serves_only_cached_content() {
+    let db_path = temp_path("budget", "sqlite");
+    let config = format!(
+        "{}\nbudget:\n  daily:\n    tokens: 1\n  exhausted_page: budget exhausted\n",
+        common::CONFIG
+    );
+    let base = spawn_server_with_config(
+        "budget",
+        Some(db_path.clone()),
+        BackendMode::Configured,
+        &config,
+    )
+    .await;
+
+    // The first generation is allowed, and spends the budget
+    let page = get(format!("{}/fruits/apples", base)).await;
+    assert!(page.contains("<h1>apples</h1>"));
+
+    let response = reqwest::get(format!("{}/fruits/pears", base))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 503);
+    assert_eq!(response.text().await.unwrap(), "budget exhausted");
+
+    assert_eq!(get(format!("{}/fruits/apples", base)).await, page);
+
+    // Spending is kept in the database, so the budget stays exhausted after a restart
+    let restarted = spawn_server_with_config(
+        "budget-restart",
+        Some(db_path),
+        BackendMode::Configured,
+        &config,
+    )
+    .await;
+    let response = reqwest::get(format!("{}/fruits/pears", restarted))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 503);
+}
+
+#[tokio::test]
+async fn test_caches_each_content_type_separately() {
+    let base = spawn_server("negotiation", None, BackendMode::Configured).await;
+    let client = reqwest::Client::new();
+    let get_as = |accept: &'static str| {
+        let request = client
+            .get(format!("{}/apples", base))
+            .header("Accept", accept)
+            .send();
+        async move {
+            let response = request.await.unwrap();
+            let content_type = response.headers()["content-type"].clone();
+            (content_type, response.text().await.unwrap())
+        }
+    };
+
+    let (content_type, json) = get_as("application/json").await;
+    assert_eq!(content_type, "application/json");
+    serde_json::from_str::<serde_json::Value>(&json).unwrap();
+
+    let (content_type, html) = get_as("text/html").await;
+    assert_eq!(content_type, "text/html; charset=utf-8");
+    assert!(html.contains("<h1>apples</h1>"));
+
+    // Both representations are served from the cache afterwards
+    assert_eq!(get_as("application/json").await.1, json);
+    assert_eq!(get_as("text/html").await.1, html);
+}
+
+#[tokio::test]
+async fn test_serves_and_manages_stored_versions() {
+    let db_path = temp_path("versions", "sqlite");
+    let base = spawn_server("versions", Some(db_path.clone()), BackendMode::Configured).await;
+    let client = reqwest::Client::new();
+
+    let first = get(format!("{}/fruits/apples", base)).await;
+
+    // Simulate a regeneration by storing a second version directly
+    let conn = Connection::open(&db_path).unwrap();
+    conn.execute_batch(
+        "INSERT INTO resource_versions (path, query, mime_type, version, content)
+            VALUES ('/fruits/apples', '', 'text/html', 2, 'second');
+        UPDATE resources SET content = 'second', version = 2 WHERE path = '/fruits/apples';",
+    )
+    .unwrap();
+
+    assert_eq!(get(format!("{}/fruits/apples", base)).await, "second");
+    assert_eq!(
+        get(format!("{}/fruits/apples?__version=1", base)).await,
+        first
+    );
+    let missing = reqwest::get(format!("{}/fruits/apples?__version=3", base))
+        .await
+        .unwrap();
+    assert_eq!(missing.status(), 404);
+
+    let versions: serde_json::Value = client
+        .get(format!("{}/_websim/versions?path=/fruits/apples", base))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(versions.as_array().unwrap().len(), 2);
+    assert_eq!(versions[1]["current"], true);
+
+    let diff = get(format!(
+        "{}/_websim/versions/diff?path=/fruits/apples&from=1&to=2",
+        base
+    ))
+    .await;
+    assert!(diff.contains("+second"));
+
+    let response = client
+        .post(format!(
+            "{}/_websim/versions/rollback?path=/fruits/apples&version=1",
+            base
+        ))
+        .bearer_auth(ADMIN_TOKEN)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 204);
+    assert_eq!(get(format!("{}/fruits/apples", base)).await, first);
+}
+
+#[tokio::test]
+async fn test_pinned_versions_are_served_past_their_ttl() {
+    let db_path = temp_path("pinned-ttl", "sqlite");
+    let config = format!("{}    ttl_secs: 300\n", common::CONFIG);
+    let base = spawn_server_with_config(
+        "pinned-ttl",
+        Some(db_path.clone()),
+        BackendMode::Configured,
+        &config,
+    )
+    .await;
+
+    let first = get(format!("{}/fruits.json", base)).await;
+    let response = reqwest::Client::new()
+        .post(format!(
+            "{}/_websim/versions/pin?path=/fruits.json&mime_type=application/json&version=1",
+            base
+        ))
+        .bearer_auth(ADMIN_TOKEN)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 204);
+
+    let conn = Connection::open(&db_path).unwrap();
+    conn.execute("UPDATE resources SET updated_at = updated_at - 600", [])
+        .unwrap();
+
+    assert_eq!(get(format!("{}/fruits.json", base)).await, first);
+    let generations: i64 = conn
+        .query_row("SELECT COUNT(*) FROM usage", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(generations, 1);
+}
+
+#[tokio::test]
+async fn test_admin_endpoints_changing_content_need_the_token() {
+    let base = spawn_server("admin-token", None, BackendMode::Configured).await;
+    let client = reqwest::Client::new();
+    let purge = format!("{}/_websim/resources?all=true", base);
+    let pin = format!("{}/_websim/versions/pin?path=/&version=1", base);
+
+    for request in [client.delete(&purge), client.post(&pin)] {
+        assert_eq!(request.send().await.unwrap().status(), 401);
+    }
+    let response = client
+        .delete(&purge)
+        .bearer_auth("guess")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 401);
+    let response = client
+        .delete(&purge)
+        .bearer_auth(ADMIN_TOKEN)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let response = reqwest::get(format!("{}/_websim/usage", base))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+
+    // Without a token configured they are disabled
+    let config = common::CONFIG.replace("admin:\n  token: secret\n", "");
+    let base =
+        spawn_server_with_config("admin-disabled", None, BackendMode::Configured, &config).await;
+    let response = client
+        .delete(format!("{}/_websim/resources?all=true", base))
+        .bearer_auth(ADMIN_TOKEN)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 403);
+}
+
+#[tokio::test]
+async fn test_regenerates_on_no_cache_and_purge() {
+    let db_path = temp_path("purge", "sqlite");
+    let base = spawn_server("purge", Some(db_path.clone()), BackendMode::Configured).await;
+    let client = reqwest::Client::new();
+    let conn = Connection::open(&db_path).unwrap();
+    let mark_cached = || {
+        conn.execute("UPDATE resources SET content = 'cached'", [])
+            .unwrap()
+    };
+
+    let page = get(format!("{}/fruits/apples", base)).await;
+    get(format!("{}/fruits/apples.svg", base)).await;
+    get(format!("{}/vegetables/leeks", base)).await;
+    assert_eq!(mark_cached(), 3);
+
+    // A hard reload regenerates the page
+    let body = client
+        .get(format!("{}/fruits/apples", base))
+        .header("Cache-Control", "no-cache")
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert_eq!(body, page);
+    mark_cached();
+
+    // Purging requires a filter
+    let response = client
+        .delete(format!("{}/_websim/resources", base))
+        .bearer_auth(ADMIN_TOKEN)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+
+    let purged: serde_json::Value = client
+        .delete(format!("{}/_websim/resources?prefix=/fruits", base))
+        .bearer_auth(ADMIN_TOKEN)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(purged["purged"], 2);
+
+    assert_eq!(get(format!("{}/fruits/apples", base)).await, page);
+    assert_eq!(get(format!("{}/vegetables/leeks", base)).await, "cached");
+}
+
+#[tokio::test]
+async fn test_conditional_requests_return_not_modified() {
+    let base = spawn_server("conditional", None, BackendMode::Configured).await;
+    let client = reqwest::Client::new();
+    let url = format!("{}/fruits/apples", base);
+
+    // Validators are only sent once content is served from the cache
+    get(url.clone()).await;
+    let response = client.get(&url).send().await.unwrap();
+    assert_eq!(response.headers()["cache-control"], "no-cache");
+    let etag = response.headers()["etag"].clone();
+    let last_modified = response.headers()["last-modified"].clone();
+    response.text().await.unwrap();
+
+    let response = client
+        .get(&url)
+        .header("If-None-Match", etag.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 304);
+    assert_eq!(response.headers()["etag"], etag);
+    assert!(response.text().await.unwrap().is_empty());
+
+    let response = client
+        .get(&url)
+        .header("If-Modified-Since", last_modified)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 304);
+
+    let response = client
+        .get(&url)
+        .header("If-None-Match", "\"stale\"")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert!(response.text().await.unwrap().contains("<h1>apples</h1>"));
+}
+
+#[tokio::test]
+async fn test_exports_static_site() {
+    let db_path = temp_path("export", "sqlite");
+    let out = temp_path("export", "dist");
+    let _ = std::fs::remove_dir_all(&out);
+    let base = spawn_server("export", Some(db_path.clone()), BackendMode::Configured).await;
+
+    for path in [
+        "/",
+        "/fruits/apples",
+        "/fruits/apples.svg",
+        "/fruits/apples?color=green",
+        "/fruits/apples/index.html",
+    ] {
+        get(format!("{}{}", base, path)).await;
+    }
+
+    let db = Database::new(Some(db_path)).unwrap();
+    let manifest = websim::export_site(&db, &out).await.unwrap();
+    let files: Vec<&str> = manifest.resources.iter().map(|r| r.file.as_str()).collect();
+    assert_eq!(
+        files,
+        [
+            "index.html",
+            "fruits/apples/index.html",
+            "fruits/apples/index@color=green.html",
+            "fruits/apples.svg"
+        ]
+    );
+    assert!(out.join(websim::MANIFEST_FILE).exists());
+
+    // The page at /fruits/apples/index.html would overwrite the one at /fruits/apples, so is skipped
+    let page = std::fs::read_to_string(out.join("fruits/apples/index.html")).unwrap();
+    assert!(page.contains("path=/fruits/apples "), "{}", page);
+
+    // Links to exported resources are relative to the page, others are left as they were
+    let page = std::fs::read_to_string(out.join("fruits/apples/index.html")).unwrap();
+    assert!(page.contains(r#"<img src="../apples.svg""#));
+    assert!(page.contains(r#"<a href="../../index.html">Home</a>"#));
+    assert!(page.contains(r#"<a href="./apples/details">"#));
+    assert!(
+        std::fs::read_to_string(out.join("fruits/apples.svg"))
+            .unwrap()
+            .starts_with("<svg")
+    );
+}
+
+#[tokio::test]
+async fn test_serves_imported_files_without_regenerating() {
+    let db_path = temp_path("import", "sqlite");
+    let config_path = temp_path("import", "yml");
+    std::fs::write(&config_path, common::CONFIG).unwrap();
+    let dir = temp_path("import", "site");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("about")).unwrap();
+    std::fs::write(dir.join("index.html"), "<h1>Home</h1>").unwrap();
+    std::fs::write(dir.join("about/index.html"), "<h1>About us</h1>").unwrap();
+    std::fs::write(dir.join("logo.svg"), "<svg></svg>").unwrap();
+    std::fs::write(dir.join("style.css"), "body{}").unwrap();
+    std::fs::write(dir.join(".hidden.html"), "hidden").unwrap();
+
+    let db = Database::new(Some(db_path.clone())).unwrap();
+    let imported = websim::import_dir(&db, &config_path, &dir).await.unwrap();
+    let paths: Vec<&str> = imported.iter().map(|f| f.path.as_str()).collect();
+    // CSS isn't configured in the test config, so is skipped
+    assert_eq!(paths, ["/about", "/", "/logo.svg"]);
+
+    let base = spawn_server("import", Some(db_path), BackendMode::Configured).await;
+    assert_eq!(get(format!("{}/", base)).await, "<h1>Home</h1>");
+    assert_eq!(get(format!("{}/logo.svg", base)).await, "<svg></svg>");
+
+    // A hard reload doesn't replace imported content
+    let body = reqwest::Client::new()
+        .get(format!("{}/about", base))
+        .header("Cache-Control", "no-cache")
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert_eq!(body, "<h1>About us</h1>");
+}
+
+#[tokio::test]
+async fn test_warc_round_trip() {
+    let db_path = temp_path("warc", "sqlite");
+    let warc_path = temp_path("warc", "warc.gz");
+    let base = spawn_server("warc", Some(db_path.clone()), BackendMode::Configured).await;
+
+    let page = get(format!("{}/fruits/apples?color=green", base)).await;
+    let logo = get(format!("{}/logo.svg", base)).await;
+
+    let db = Database::new(Some(db_path)).unwrap();
+    let archived = websim::export_warc(&db, &warc_path, websim::DEFAULT_BASE_URL)
+        .await
+        .unwrap();
+    assert_eq!(archived, 2);
+
+    let seeded_path = temp_path("warc-seeded", "sqlite");
+    let seeded_db = Database::new(Some(seeded_path.clone())).unwrap();
+    let imported = websim::import_warc(&seeded_db, &warc_path, None)
+        .await
+        .unwrap();
+    let uris: Vec<&str> = imported.iter().map(|r| r.uri.as_str()).collect();
+    assert_eq!(
+        uris,
+        [
+            "http://localhost:3000/fruits/apples?color=green",
+            "http://localhost:3000/logo.svg"
+        ]
+    );
+
+    let seeded = spawn_server("warc-seeded", Some(seeded_path), BackendMode::Configured).await;
+    assert_eq!(
+        get(format!("{}/fruits/apples?color=green", seeded)).await,
+        page
+    );
+    assert_eq!(get(format!("{}/logo.svg", seeded)).await, logo);
+}
+
+#[tokio::test]
+async fn test_crawl_generates_linked_pages() {
+    let db_path = temp_path("crawl", "sqlite");
+    let config_path = temp_path("crawl", "yml");
+    std::fs::write(&config_path, common::CONFIG).unwrap();
+    let crawl = |max_pages| {
+        let options = websim::CrawlOptions {
+            depth: 1,
+            max_pages,
+            concurrency: 4,
+            site: None,
+        };
+        let (db_path, config_path) = (db_path.clone(), config_path.clone());
+        async move {
+            websim::crawl(
+                db_path,
+                config_path,
+                BackendMode::Configured,
+                "/fruits/apples",
+                &options,
+            )
+            .await
+            .unwrap()
+        }
+    };
+
+    assert_eq!(crawl(3).await.len(), 3);
+
+    // Links are followed in the order they appear, skipping types the config has no content type for
+    let pages = crawl(20).await;
+    let crawled: Vec<(&str, u16)> = pages
+        .iter()
+        .map(|page| (page.path_and_query.as_str(), page.status))
+        .collect();
+    assert_eq!(
+        crawled,
+        [
+            ("/fruits/apples", 200),
+            ("/style.css", 404),
+            ("/fruits/apples.svg", 200),
+            ("/fruits/apples/details", 200),
+            ("/", 200),
+            ("/app.js", 404)
+        ]
+    );
+
+    let conn = Connection::open(&db_path).unwrap();
+    let stored: i64 = conn
+        .query_row("SELECT COUNT(*) FROM resources", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(stored, 4);
+}
+
+#[tokio::test]
+async fn test_prefetches_linked_pages_within_spending_cap() {
+    let db_path = temp_path("prefetch", "sqlite");
+    let config = format!(
+        "{}\nprefetch:\n  daily:\n    tokens: 1\n",
+        common::CONFIG.replace(
+            "    cache_control: no-cache\n",
+            "    cache_control: no-cache\n    prefetch_links: 1\n"
+        )
+    );
+    let base = spawn_server_with_config(
+        "prefetch",
+        Some(db_path.clone()),
+        BackendMode::Configured,
+        &config,
+    )
+    .await;
+    let conn = Connection::open(&db_path).unwrap();
+    let generations = |path: &str| -> i64 {
+        conn.query_row(
+            "SELECT COUNT(*) FROM usage WHERE path = ?1",
+            [path],
+            |row| row.get(0),
+        )
+        .unwrap()
+    };
+
+    get(format!("{}/fruits/apples", base)).await;
+    for _ in 0..50 {
+        if generations("/fruits/apples/details") > 0 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    assert_eq!(generations("/fruits/apples/details"), 1);
+    // Only the first link is prefetched
+    assert_eq!(generations("/"), 0);
+
+    // Following the link is served from the cache
+    get(format!("{}/fruits/apples/details", base)).await;
+    assert_eq!(generations("/fruits/apples/details"), 1);
+
+    // The prefetch spent the speculative budget, so links are no longer prefetched
+    get(format!("{}/fruits/pears", base)).await;
+    tokio::time::sleep(std::time::Duration::from_millis(1000)).await;
+    assert_eq!(generations("/fruits/pears/details"), 0);
+
+    let speculative: i64 = conn
+        .query_row("SELECT COUNT(*) FROM usage WHERE speculative", [], |row| {
+            row.get(0)
+        })
+        .unwrap();
+    assert_eq!(speculative, 1);
+}
+
+#[tokio::test]
+async fn test_routes_override_matching_paths() {
+    let db_path = temp_path("routes", "sqlite");
+    let config = format!(
+        r#"{}
+routes:
+  - path: /api/**
+    content_type: application/json
+    reference_materials: false
+  - regex: ^/blog/[^/]+/comments$
+    model: comments-model
+    system_prompt: comments
+"#,
+        common::CONFIG
+    );
+    let base = spawn_server_with_config(
+        "routes",
+        Some(db_path.clone()),
+        BackendMode::Configured,
+        &config,
+    )
+    .await;
+
+    // Matching paths are served as JSON without negotiating, and without reference materials
+    get(format!("{}/api", base)).await;
+    let response = reqwest::get(format!("{}/api/fruits", base)).await.unwrap();
+    assert_eq!(response.headers()["content-type"], "application/json");
+    let json: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(json["references"], serde_json::json!([]));
+
+    get(format!("{}/blog/first", base)).await;
+    get(format!("{}/blog/first/comments", base)).await;
+    let conn = Connection::open(&db_path).unwrap();
+    let model = |path: &str| -> String {
+        conn.query_row("SELECT model FROM usage WHERE path = ?1", [path], |row| {
+            row.get(0)
+        })
+        .unwrap()
+    };
+    assert_eq!(model("/blog/first"), "mock");
+    assert_eq!(model("/blog/first/comments"), "comments-model");
+}
+
+#[tokio::test]
+async fn test_sites_by_host_and_path_prefix() {
+    let db_path = temp_path("sites", "sqlite");
+    let config = format!(
+        r#"{}
+sites:
+  news:
+    hosts: [news.localhost]
+    world:
+      description: A fake news site
+  api:
+    path_prefix: /api
+    content_types:
+      application/json:
+        backend: mock
+        model: mock
+        system_prompt: json
+        content_type_header: "application/json"
+        extensions: [json]
+    routes:
+      - path: /api/**
+        content_type: application/json
+"#,
+        common::CONFIG
+    );
+    let base = spawn_server_with_config(
+        "sites",
+        Some(db_path.clone()),
+        BackendMode::Configured,
+        &config,
+    )
+    .await;
+    let client = reqwest::Client::new();
+    let get_news = |path: &str| {
+        let request = client
+            .get(format!("{}{}", base, path))
+            .header("Host", "news.localhost:3000")
+            .send();
+        async move { request.await.unwrap().text().await.unwrap() }
+    };
+
+    // Each site has its own pages, so pages on one aren't reference material for another
+    get(format!("{}/fruits", base)).await;
+    let page = get_news("/fruits/apples").await;
+    assert!(!page.contains("<!-- reference: /fruits"));
+    get_news("/fruits").await;
+    let page = get_news("/fruits/pears").await;
+    assert!(page.contains("<!-- reference: /fruits (parent) -->"));
+
+    let response = reqwest::get(format!("{}/api/fruits", base)).await.unwrap();
+    assert_eq!(response.headers()["content-type"], "application/json");
+    let json: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(json["path"], "/api/fruits");
+
+    let conn = Connection::open(&db_path).unwrap();
+    let paths = |site: &str| -> Vec<String> {
+        let mut stmt = conn
+            .prepare("SELECT path FROM resources WHERE site = ?1 ORDER BY path")
+            .unwrap();
+        stmt.query_map([site], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    };
+    assert_eq!(paths(""), ["/fruits"]);
+    assert_eq!(
+        paths("news"),
+        ["/fruits", "/fruits/apples", "/fruits/pears"]
+    );
+    assert_eq!(paths("api"), ["/api/fruits"]);
+}
+
+#[tokio::test]
+async fn test_world_facts_are_recorded_and_stripped() {
+    let db_path = temp_path("facts", "sqlite");
+    let config = format!(
+        r#"{}
+world:
+  name: Fruit Market
+  facts: true
+"#,
+        common::CONFIG
+    );
+    let base = spawn_server_with_config(
+        "facts",
+        Some(db_path.clone()),
+        BackendMode::Configured,
+        &config,
+    )
+    .await;
+
+    // The facts block is kept out of streamed responses and stored content
+    let page = get(format!("{}/fruits", base)).await;
+    assert!(page.ends_with("</html>"), "{}", page);
+    let response = reqwest::get(format!("{}/apples.json", base)).await.unwrap();
+    let json: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(json["subject"], "apples");
+
+    let conn = Connection::open(&db_path).unwrap();
+    let stored: i64 = conn
+        .query_row(
+            "SELECT COUNT(*) FROM resources WHERE content LIKE '%websim-facts%'",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(stored, 0);
+
+    let mut stmt = conn
+        .prepare("SELECT name, path FROM facts ORDER BY name")
+        .unwrap();
+    let facts: Vec<(String, String)> = stmt
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(
+        facts,
+        [
+            ("apples".to_string(), "/apples.json".to_string()),
+            ("fruits".to_string(), "/fruits".to_string())
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_user_prompt_templates() {
+    // Template files are read relative to the config file, which is written to the same directory
+    let template_path = temp_path("templates-json", "j2");
+    std::fs::write(
+        &template_path,
+        "Generate {{ path }}\n### {{ mime_type }} {{ path_and_query }}\n",
+    )
+    .unwrap();
+    let config = format!(
+        r#"
+backends:
+  mock:
+    type: mock
+
+user_prompt_template: |
+  Generate {{{{ path }}}}
+  ### {{{{ method }}}} {{{{ path }}}} {{{{ query }}}} {{{{ headers['x-fruit'] }}}}
+
+content_types:
+  text/html:
+    backend: mock
+    model: mock
+    system_prompt: html
+    content_type_header: "text/html; charset=utf-8"
+    extensions: [html]
+  application/json:
+    backend: mock
+    model: mock
+    system_prompt: json
+    content_type_header: "application/json"
+    extensions: [json]
+    user_prompt_template:
+      file: {}
+"#,
+        template_path.file_name().unwrap().to_str().unwrap()
+    );
+    let base = spawn_server_with_config("templates", None, BackendMode::Configured, &config).await;
+
+    let page = reqwest::Client::new()
+        .get(format!("{}/fruits?color=red", base))
+        .header("X-Fruit", "apple")
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(page.contains("<!-- reference: GET /fruits color=red apple -->"));
+
+    let response = reqwest::get(format!("{}/apples.json?ripe=1", base))
+        .await
+        .unwrap();
+    let json: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(
+        json["references"],
+        serde_json::json!(["application/json /apples.json?ripe=1"])
+    );
+}
+
+#[tokio::test]
+async fn test_invalid_user_prompt_template_fails_startup() {
+    let config_path = temp_path("invalid-template", "yml");
+    std::fs::write(
+        &config_path,
+        format!(
+            "{}\nuser_prompt_template: \"{{% if path %}}\"\n",
+            common::CONFIG
+        ),
+    )
+    .unwrap();
+
+    let error = websim::build_app(None, config_path, BackendMode::Configured)
+        .await
+        .err()
+        .unwrap();
+    assert!(format!("{:#}", error).contains("Invalid user_prompt_template"));
+}
+
+#[tokio::test]
+async fn test_admin_endpoints_on_path_prefix_site() {
+    let db_path = temp_path("prefix-admin", "sqlite");
+    let config = format!(
+        r#"{}
+sites:
+  api:
+    path_prefix: /api
+"#,
+        common::CONFIG
+    );
+    let base = spawn_server_with_config(
+        "prefix-admin",
+        Some(db_path.clone()),
+        BackendMode::Configured,
+        &config,
+    )
+    .await;
+    let client = reqwest::Client::new();
+
+    get(format!("{}/api/fruits", base)).await;
+    client
+        .get(format!("{}/api/fruits", base))
+        .header("Cache-Control", "no-cache")
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+
+    let versions: serde_json::Value = client
+        .get(format!("{}/api/_websim/versions?path=/api/fruits", base))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(versions.as_array().unwrap().len(), 2);
+
+    // The default site's admin endpoints don't reach the prefix site's pages
+    let purged: serde_json::Value = client
+        .delete(format!("{}/_websim/resources?prefix=/api", base))
+        .bearer_auth(ADMIN_TOKEN)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(purged["purged"], 0);
+
+    let purged: serde_json::Value = client
+        .delete(format!("{}/api/_websim/resources?prefix=/api", base))
+        .bearer_auth(ADMIN_TOKEN)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(purged["purged"], 1);
+
+    let conn = Connection::open(&db_path).unwrap();
+    let admin_pages: i64 = conn
+        .query_row(
+            "SELECT COUNT(*) FROM resources WHERE path LIKE '%_websim%'",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(admin_pages, 0);
+}
+
+#[tokio::test]
+async fn test_commands_work_on_named_sites() {
+    let db_path = temp_path("site-commands", "sqlite");
+    let config_path = temp_path("site-commands", "yml");
+    let out = temp_path("site-commands", "dist");
+    let _ = std::fs::remove_dir_all(&out);
+    std::fs::write(
+        &config_path,
+        format!(
+            r#"{}
+sites:
+  news:
+    hosts: [news.localhost]
+  api:
+    path_prefix: /api
+"#,
+            common::CONFIG
+        ),
+    )
+    .unwrap();
+    let crawl = |site: &str, seed: &'static str| {
+        let options = websim::CrawlOptions {
+            depth: 0,
+            max_pages: 10,
+            concurrency: 1,
+            site: Some(site.to_string()),
+        };
+        let (db_path, config_path) = (db_path.clone(), config_path.clone());
+        async move {
+            websim::crawl(
+                db_path,
+                config_path,
+                BackendMode::Configured,
+                seed,
+                &options,
+            )
+            .await
+            .unwrap()
+        }
+    };
+
+    // Crawls reach a site by its first host, or else under its path prefix
+    crawl("news", "/fruits").await;
+    let pages = crawl("api", "/fruits").await;
+    assert_eq!(pages[0].path_and_query, "/api/fruits");
+
+    let open = |site: &str| websim::open_site_database(db_path.clone(), &config_path, site);
+    let paths = |db: Database| async move {
+        db.resources()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|resource| resource.path)
+            .collect::<Vec<_>>()
+    };
+    assert!(paths(open("").unwrap()).await.is_empty());
+    assert_eq!(paths(open("news").unwrap()).await, ["/fruits"]);
+    assert_eq!(paths(open("api").unwrap()).await, ["/api/fruits"]);
+    assert!(open("sports").is_err());
+
+    let manifest = websim::export_site(&open("news").unwrap(), &out)
+        .await
+        .unwrap();
+    assert_eq!(manifest.resources.len(), 1);
+    assert_eq!(manifest.resources[0].path, "/fruits");
+}